@@ -0,0 +1,312 @@
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::*;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::config;
+use crate::lcu::LCUClient;
+use crate::{champ_arg, setup_runes_and_spells, status, Candidate, Job, CHAMPIONS};
+
+/// How long `/apply` waits for the event loop to get to it.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ApplyRequest {
+    /// Defaults to the champion in champ select
+    champion: Option<String>,
+    /// Defaults to the current game mode
+    mode: Option<String>,
+}
+
+/// Read the token from `path`, or make one if there isn't one yet. It stays the same across
+/// restarts, so buttons and overlays set up with it keep working.
+fn load_token(path: &Path) -> Result<String> {
+    if let Ok(token) = fs::read_to_string(path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let mut bytes = [0u8; 24];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow!("couldn't make an API token: {}", e))?;
+    let token = BASE64_URL_SAFE_NO_PAD.encode(bytes);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .with_context(|| format!("couldn't write API token to {}", path.display()))?;
+    log!("made a new API token in {}", path.display());
+    Ok(token)
+}
+
+/// Serve the API on localhost from a background thread. `/apply` requests are sent to `jobs`.
+pub fn start(port: u16, token_path: &Path, jobs: Sender<Job>) -> Result<()> {
+    let token = load_token(token_path)?;
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| anyhow!("couldn't listen on port {}: {}", port, e))?;
+    log!(
+        "API listening on http://127.0.0.1:{}, token in {}",
+        port,
+        token_path.display()
+    );
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let access = check_access(&request, &token);
+            let (code, body) = match &access {
+                Ok(origin) if request.method() == &Method::Options => {
+                    let mut response = Response::empty(204);
+                    for header in cors_headers(origin.as_deref()) {
+                        response.add_header(header);
+                    }
+                    if let Err(e) = request.respond(response) {
+                        log!("couldn't answer API request: {}", e);
+                    }
+                    continue;
+                }
+                Ok(origin) if request.method() == &Method::Get && path(&request) == "/events" => {
+                    let origin = origin.clone();
+                    // The stream stays open, so it can't hold up other requests
+                    thread::spawn(move || {
+                        if let Err(e) = stream_events(request, origin.as_deref()) {
+                            log!("event stream closed: {}", e);
+                        }
+                    });
+                    continue;
+                }
+                Ok(_) => match handle(&mut request, &jobs) {
+                    Ok(response) => response,
+                    Err(e) => (500, json!({ "error": e.to_string() })),
+                },
+                Err(denied) => denied.clone(),
+            };
+            let mut response = Response::from_string(body.to_string())
+                .with_status_code(code)
+                .with_header(content_type("application/json"));
+            for header in cors_headers(access.ok().flatten().as_deref()) {
+                response.add_header(header);
+            }
+            if let Err(e) = request.respond(response) {
+                log!("couldn't answer API request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("valid header")
+}
+
+/// Headers that let an allowed web page read the response.
+fn cors_headers(origin: Option<&str>) -> Vec<Header> {
+    let Some(origin) = origin else {
+        return Vec::new();
+    };
+    [
+        ("Access-Control-Allow-Origin", origin),
+        ("Access-Control-Allow-Methods", "GET, POST"),
+        (
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type",
+        ),
+        ("Vary", "Origin"),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Header::from_bytes(field, value).ok())
+    .collect()
+}
+
+fn path(request: &Request) -> &str {
+    request.url().split('?').next().unwrap_or_default()
+}
+
+/// Browsers send an Origin header with requests from web pages, only the ones in `api_origins`
+/// are let in. Everything else needs the token, as a bearer token or, for `/events`, in a
+/// `token` query parameter since `EventSource` can't set headers. Preflight requests don't carry
+/// the token, the browser sends it with the request that follows. Returns the allowed origin, if
+/// any, or the error response.
+fn check_access(request: &Request, token: &str) -> Result<Option<String>, (u16, Value)> {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.as_str())
+    };
+    let origin = header("Origin");
+    if let Some(origin) = origin {
+        if !config().api_origins.iter().any(|allowed| allowed == origin) {
+            return Err((
+                403,
+                json!({ "error": format!("origin {} isn't in api_origins", origin) }),
+            ));
+        }
+    }
+    let origin = origin.map(str::to_string);
+    if request.method() == &Method::Options {
+        return Ok(origin);
+    }
+    let query_token = match request.url().split_once('?') {
+        Some(("/events", query)) => query
+            .split('&')
+            .find_map(|param| param.strip_prefix("token=")),
+        _ => None,
+    };
+    if header("Authorization") != Some(format!("Bearer {}", token).as_str())
+        && query_token != Some(token)
+    {
+        return Err((401, json!({ "error": "missing or wrong API token" })));
+    }
+    Ok(origin)
+}
+
+fn handle(request: &mut Request, jobs: &Sender<Job>) -> Result<(u16, Value)> {
+    match (request.method(), path(request)) {
+        (Method::Get, "/status") => Ok((200, status_json())),
+        (Method::Post, "/apply") => {
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body)?;
+            let apply: ApplyRequest = if body.trim().is_empty() {
+                ApplyRequest::default()
+            } else {
+                serde_json::from_str(&body)?
+            };
+            let (reply, answer) = channel();
+            let received = Instant::now();
+            // Answer the request, unless the API already stopped waiting
+            jobs.send(Box::new(move |lcuclient, conn, dry_run| {
+                if received.elapsed() > APPLY_TIMEOUT {
+                    log!("dropping an API apply request that waited too long");
+                    return;
+                }
+                let _ = reply.send(apply_pages(lcuclient, conn, apply, dry_run));
+            }))
+            .map_err(|_| anyhow!("the event loop isn't running"))?;
+            match answer.recv_timeout(APPLY_TIMEOUT) {
+                Ok(response) => response,
+                Err(_) => Ok((
+                    503,
+                    json!({ "error": "pages weren't set up in time, is the client running?" }),
+                )),
+            }
+        }
+        (Method::Post, "/pause") => {
+            status::set_paused(true);
+            Ok((200, json!({ "paused": true })))
+        }
+        (Method::Post, "/resume") => {
+            status::set_paused(false);
+            Ok((200, json!({ "paused": false })))
+        }
+        (_, url) => Ok((404, json!({ "error": format!("no such endpoint {}", url) }))),
+    }
+}
+
+fn status_json() -> Value {
+    let status = status::get();
+    let to_json = |candidates: &[Candidate]| -> Vec<Value> {
+        candidates
+            .iter()
+            .map(|candidate| {
+                json!({
+                    "name": candidate.page.name,
+                    "id": (candidate.page.id != 0).then_some(candidate.page.id),
+                    "source": candidate.source,
+                    "winRate": candidate.win_rate,
+                    "score": candidate.score,
+                    "dropped": candidate.dropped.as_ref().map(ToString::to_string),
+                    "role": candidate.role,
+                    "vs": candidate.vs,
+                    "spells": candidate.spells,
+                })
+            })
+            .collect()
+    };
+    json!({
+        "phase": status.phase.map(|phase| format!("{:?}", phase)),
+        "gameMode": status.game_mode,
+        "champId": status.champ_id,
+        "champion": status.champ_id.and_then(|id| CHAMPIONS.get(&id)),
+        "position": status.position,
+        "enemies": status.opponents.enemies,
+        "laner": status.opponents.laner,
+        "spells": status.spells,
+        "runePage": status.rune_page.as_ref().map(|page| &page.name),
+        "paused": status.paused,
+        // Candidates can only have been dropped for lack of space, so they can still be picked
+        "candidates": to_json(&status.candidates),
+        "dropped": to_json(&status.dropped),
+    })
+}
+
+fn apply_pages(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    apply: ApplyRequest,
+    dry_run: bool,
+) -> Result<(u16, Value)> {
+    let (champ_id, game_mode, position, opponents) = {
+        let status = status::get();
+        (
+            status.champ_id,
+            status.game_mode.clone(),
+            status.position,
+            status.opponents.clone(),
+        )
+    };
+    let champ_id = match apply.champion {
+        Some(champion) => champ_arg(&champion)?,
+        None => match champ_id {
+            Some(champ_id) => champ_id,
+            None => return Ok((409, json!({ "error": "no champion picked" }))),
+        },
+    };
+    let game_mode = apply
+        .mode
+        .or(game_mode)
+        .unwrap_or_else(|| "UNKNOWN".to_string());
+    setup_runes_and_spells(
+        lcuclient, conn, champ_id, &game_mode, position, &opponents, dry_run,
+    )?;
+    Ok((200, status_json()))
+}
+
+/// Send events as Server-Sent Events until the client goes away.
+fn stream_events(request: Request, origin: Option<&str>) -> Result<()> {
+    let events = status::subscribe();
+    let mut writer = request.into_writer();
+    // Written by hand, tiny_http's chunked responses hold back small writes
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+            Connection: close\r\n"
+    )?;
+    for header in cors_headers(origin) {
+        write!(writer, "{}: {}\r\n", header.field, header.value)?;
+    }
+    write!(writer, "\r\n")?;
+    writer.flush()?;
+    loop {
+        match events.recv_timeout(Duration::from_secs(15)) {
+            Ok((event, data)) => write!(writer, "event: {}\ndata: {}\n\n", event, data)?,
+            // Comments keep proxies and idle timeouts from closing the stream
+            Err(RecvTimeoutError::Timeout) => write!(writer, ": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        writer.flush()?;
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// List cached provider responses
+    List,
+    /// Remove cached provider responses
+    Clear {
+        /// Only remove responses that are past their time to live
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+struct Entry {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    expires: i64,
+}
+
+pub fn setup_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists provider_cache (
+                url text primary key on conflict replace,
+                body text not null,
+                etag text,
+                last_modified text,
+                fetched integer not null,
+                expires integer not null
+            )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn now() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+fn lookup(conn: &Connection, url: &str) -> Result<Option<Entry>> {
+    Ok(conn
+        .query_row(
+            "select body, etag, last_modified, expires from provider_cache where url = ?1",
+            params![url],
+            |row| {
+                Ok(Entry {
+                    body: row.get(0)?,
+                    etag: row.get(1)?,
+                    last_modified: row.get(2)?,
+                    expires: row.get(3)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn store(conn: &Connection, url: &str, entry: &Entry) -> Result<()> {
+    conn.execute(
+        "insert into provider_cache (url, body, etag, last_modified, fetched, expires)
+            values (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            url,
+            entry.body,
+            entry.etag,
+            entry.last_modified,
+            now()?,
+            entry.expires
+        ],
+    )?;
+    Ok(())
+}
+
+/// Ask the provider, sending along what we know about the cached copy so it can answer with
+/// 304 Not Modified instead of the whole response.
+fn request(url: &str, cached: Option<&Entry>, ttl: u64) -> Result<Entry> {
+    log!("fetching {}", url);
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(4))
+        .connect_timeout(Duration::from_secs(2))
+        .build()?;
+    let mut request = client.get(url);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request.send()?;
+    let expires = now()? + ttl as i64;
+    if let (StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), cached) {
+        log!("{} hasn't changed", url);
+        return Ok(Entry {
+            body: cached.body.clone(),
+            etag: cached.etag.clone(),
+            last_modified: cached.last_modified.clone(),
+            expires,
+        });
+    }
+    if !response.status().is_success() {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+    Ok(Entry {
+        body: response.text()?,
+        etag,
+        last_modified,
+        expires,
+    })
+}
+
+/// Get a provider response, from the cache while it's younger than `ttl` seconds. When the
+/// provider can't be reached, an expired copy is better than nothing.
+pub fn get(conn: &Connection, url: &str, ttl: u64) -> Result<String> {
+    let cached = lookup(conn, url)?;
+    if let Some(cached) = &cached {
+        if cached.expires > now()? {
+            return Ok(cached.body.clone());
+        }
+    }
+    match request(url, cached.as_ref(), ttl) {
+        Ok(entry) => {
+            store(conn, url, &entry)?;
+            Ok(entry.body)
+        }
+        Err(e) => match cached {
+            Some(cached) => {
+                log!("couldn't fetch {}, using the cached copy: {}", url, e);
+                Ok(cached.body)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+pub fn run(conn: &Connection, command: CacheCommand) -> Result<()> {
+    match command {
+        CacheCommand::List => list(conn),
+        CacheCommand::Clear { expired } => {
+            let deleted = if expired {
+                conn.execute(
+                    "delete from provider_cache where expires <= ?1",
+                    params![now()?],
+                )?
+            } else {
+                conn.execute("delete from provider_cache", [])?
+            };
+            println!("removed {} cached responses", deleted);
+            Ok(())
+        }
+    }
+}
+
+fn list(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "select url, length(body), etag, last_modified, fetched, expires from provider_cache
+            order by url",
+    )?;
+    let mut rows = stmt.query([])?;
+    let now = now()?;
+    while let Some(row) = rows.next()? {
+        let url: String = row.get(0)?;
+        let size: i64 = row.get(1)?;
+        let etag: Option<String> = row.get(2)?;
+        let last_modified: Option<String> = row.get(3)?;
+        let fetched: i64 = row.get(4)?;
+        let expires: i64 = row.get(5)?;
+        let state = if expires > now { "fresh" } else { "expired" };
+        println!("{}", url);
+        println!(
+            "  {} bytes, fetched {}s ago, {}",
+            size,
+            now - fetched,
+            state
+        );
+        if let Some(etag) = etag {
+            println!("  etag: {}", etag);
+        }
+        if let Some(last_modified) = last_modified {
+            println!("  last modified: {}", last_modified);
+        }
+    }
+    Ok(())
+}
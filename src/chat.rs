@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::config::config;
+use crate::lcu::LCUClient;
+use crate::{make_candidate_current, save_candidate, status};
+
+static PREFIX: &str = "!rp";
+
+#[derive(Debug, PartialEq)]
+pub enum ChatCommand {
+    /// Post the candidate list again
+    List,
+    /// Make a candidate current, counting from 0
+    Pick(usize),
+    /// Save a candidate, or the current page if it's one of ours
+    Save(Option<usize>),
+    Off,
+    On,
+}
+
+/// Commands look like `!rp 2`, `!rp save`, `!rp save 2`, `!rp off` and `!rp on`. Pages are
+/// numbered from 1 in chat.
+pub fn parse(body: &str) -> Option<ChatCommand> {
+    let mut words = body.split_whitespace();
+    if words.next()? != PREFIX {
+        return None;
+    }
+    let number = |word: &str| word.parse::<usize>().ok().filter(|n| *n > 0).map(|n| n - 1);
+    match (words.next(), words.next(), words.next()) {
+        (None, _, _) | (Some("list"), None, _) => Some(ChatCommand::List),
+        (Some("save"), None, _) => Some(ChatCommand::Save(None)),
+        (Some("save"), Some(n), None) => Some(ChatCommand::Save(Some(number(n)?))),
+        (Some("off"), None, _) => Some(ChatCommand::Off),
+        (Some("on"), None, _) => Some(ChatCommand::On),
+        (Some(n), None, _) => Some(ChatCommand::Pick(number(n)?)),
+        _ => None,
+    }
+}
+
+/// Our own id in chat, to tell our messages apart from the rest of the team's.
+pub fn my_id(lcuclient: &LCUClient) -> Result<String> {
+    let me = lcuclient.get("/lol-chat/v1/me")?.text()?;
+    let me: Value = serde_json::from_str(&me)?;
+    Ok(me["id"].as_str().context("no id in chat info")?.to_string())
+}
+
+fn champ_select_conversation(lcuclient: &LCUClient) -> Result<String> {
+    let conversations = lcuclient.get("/lol-chat/v1/conversations")?.text()?;
+    let conversations: Vec<Value> = serde_json::from_str(&conversations)?;
+    conversations
+        .iter()
+        .find(|conversation| conversation["type"] == "championSelect")
+        .and_then(|conversation| conversation["id"].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("no champ select chat"))
+}
+
+/// Reply to a command. Replies only go to champ select chat with `chat_replies`, since it isn't
+/// known who else gets to see them.
+fn post(lcuclient: &LCUClient, lines: &[String], dry_run: bool) -> Result<()> {
+    if !config().chat_replies {
+        for line in lines {
+            log!("{}", line);
+        }
+        return Ok(());
+    }
+    if dry_run {
+        for line in lines {
+            log!("would post in chat: {}", line);
+        }
+        return Ok(());
+    }
+    let uri = format!(
+        "/lol-chat/v1/conversations/{}/messages",
+        champ_select_conversation(lcuclient)?
+    );
+    for line in lines {
+        // Meant to only be shown to the sender, but that may not hold for every client
+        let message = json!({ "body": line, "type": "celebration" });
+        lcuclient.post(&uri, message.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn post_candidates(lcuclient: &LCUClient, dry_run: bool) -> Result<()> {
+    let mut lines: Vec<String> = status::get()
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            format!(
+                "{}. {} ({})",
+                index + 1,
+                candidate.page.name,
+                candidate.source
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        return Ok(());
+    }
+    lines.push(format!(
+        "{0} <number> to switch, {0} save to keep it, {0} off to stop",
+        PREFIX
+    ));
+    post(lcuclient, &lines, dry_run)
+}
+
+/// The candidate that is the current page in the client.
+fn current_index() -> Option<usize> {
+    let status = status::get();
+    let current = status.rune_page.as_ref()?.id;
+    status
+        .candidates
+        .iter()
+        .position(|candidate| candidate.page.id != 0 && candidate.page.id == current)
+}
+
+pub fn handle(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    command: ChatCommand,
+    dry_run: bool,
+) -> Result<()> {
+    match command {
+        ChatCommand::List => post_candidates(lcuclient, dry_run),
+        ChatCommand::Pick(index) => make_candidate_current(lcuclient, index, dry_run),
+        ChatCommand::Save(index) => {
+            let index = match index {
+                Some(index) => index,
+                None => current_index().with_context(|| {
+                    format!(
+                        "the current page isn't listed, use {} save <number>",
+                        PREFIX
+                    )
+                })?,
+            };
+            save_candidate(lcuclient, conn, index, dry_run)
+        }
+        ChatCommand::Off => {
+            status::set_paused(true);
+            let line = format!("rune_pager is off, {} on to turn it back on", PREFIX);
+            post(lcuclient, &[line], dry_run)
+        }
+        ChatCommand::On => {
+            status::set_paused(false);
+            post(lcuclient, &["rune_pager is on".to_string()], dry_run)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("!rp"), Some(ChatCommand::List));
+        assert_eq!(parse("  !rp   list "), Some(ChatCommand::List));
+        assert_eq!(parse("!rp 2"), Some(ChatCommand::Pick(1)));
+        assert_eq!(parse("!rp save"), Some(ChatCommand::Save(None)));
+        assert_eq!(parse("!rp save 3"), Some(ChatCommand::Save(Some(2))));
+        assert_eq!(parse("!rp off"), Some(ChatCommand::Off));
+        assert_eq!(parse("!rp on"), Some(ChatCommand::On));
+    }
+
+    #[test]
+    fn parse_ignores_everything_else() {
+        assert_eq!(parse("gl hf"), None);
+        assert_eq!(parse("!rp2"), None);
+        assert_eq!(parse("!RP 2"), None);
+        // Pages are numbered from 1
+        assert_eq!(parse("!rp 0"), None);
+        assert_eq!(parse("!rp save 0"), None);
+        assert_eq!(parse("!rp -1"), None);
+        assert_eq!(parse("!rp 2 please"), None);
+        assert_eq!(parse("!rp save 2 3"), None);
+        assert_eq!(parse("!rp list all"), None);
+    }
+}
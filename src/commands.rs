@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection};
+
+use crate::lcu::LCUClient;
+use crate::{choose_candidates, get_patch, sources, spells, Candidate, RunePage, CHAMPIONS};
+
+struct StoredRow {
+    table: &'static str,
+    champ_id: u64,
+    game_mode: String,
+    spells: (u64, u64),
+    page: RunePage,
+    patch: Option<String>,
+    pinned: bool,
+}
+
+/// All stored pages, optionally for just one champion and/or game mode. Pages in the history
+/// table come after the one for the same champion and mode in `rune_pages`.
+fn stored_rows(
+    conn: &Connection,
+    champ_id: Option<u64>,
+    game_mode: Option<&str>,
+) -> Result<Vec<StoredRow>> {
+    let mut rows = Vec::new();
+    for (table, pinned) in [("rune_pages", "pinned"), ("rune_page_history", "0")] {
+        let mut stmt = conn.prepare(&format!(
+            "select champ_id, game_mode, spell1_id, spell2_id, page, patch, {} from {}
+                where (?1 is null or champ_id = ?1) and (?2 is null or game_mode = ?2)",
+            pinned, table
+        ))?;
+        let mut result = stmt.query(params![champ_id.map(|id| id as i64), game_mode])?;
+        while let Some(row) = result.next()? {
+            let champ_id: i64 = row.get(0)?;
+            let spells: (i64, i64) = (row.get(2)?, row.get(3)?);
+            let page: String = row.get(4)?;
+            rows.push(StoredRow {
+                table,
+                champ_id: champ_id as u64,
+                game_mode: row.get(1)?,
+                spells: (spells.0 as u64, spells.1 as u64),
+                page: serde_json::from_str(&page)?,
+                patch: row.get(5)?,
+                pinned: row.get(6)?,
+            });
+        }
+    }
+    let champion = |row: &StoredRow| CHAMPIONS.get(&row.champ_id).copied().unwrap_or("?");
+    rows.sort_by(|a, b| {
+        (champion(a), &a.game_mode, a.table).cmp(&(champion(b), &b.game_mode, b.table))
+    });
+    Ok(rows)
+}
+
+fn describe(row: &StoredRow) -> String {
+    let mut flags = Vec::new();
+    if row.pinned {
+        flags.push("pinned".to_string());
+    }
+    if row.table == "rune_page_history" {
+        flags.push("last played".to_string());
+    }
+    if let Some(patch) = &row.patch {
+        flags.push(patch.clone());
+    }
+    format!(
+        "{:<16} {:<10} spells {:>2} {:>2}  {}  [{}]",
+        CHAMPIONS.get(&row.champ_id).copied().unwrap_or("?"),
+        row.game_mode,
+        row.spells.0,
+        row.spells.1,
+        row.page.name,
+        flags.join(", ")
+    )
+}
+
+pub fn list(conn: &Connection, champ_id: Option<u64>) -> Result<()> {
+    let rows = stored_rows(conn, champ_id, None)?;
+    for row in &rows {
+        println!("{}", describe(row));
+    }
+    println!("{} stored pages", rows.len());
+    Ok(())
+}
+
+fn print_page(page: &RunePage, spells: (u64, u64)) {
+    println!("  name:    {}", page.name);
+    println!(
+        "  styles:  {} / {}",
+        page.primary_style_id, page.sub_style_id
+    );
+    println!("  perks:   {:?}", page.selected_perk_ids);
+    println!(
+        "  spells:  {} {}",
+        spells::name(spells.0),
+        spells::name(spells.1)
+    );
+}
+
+fn print_candidate(candidate: &Candidate) {
+    print_page(&candidate.page, candidate.spells);
+    let mut source = candidate.source.clone();
+    if let Some(role) = candidate.role {
+        source = format!("{} ({})", source, role);
+    }
+    if let Some(vs) = candidate.vs {
+        source = format!(
+            "{} vs {}",
+            source,
+            CHAMPIONS.get(&vs).copied().unwrap_or("?")
+        );
+    }
+    match candidate.win_rate {
+        Some(win_rate) => println!("  source:  {}, win rate {}", source, win_rate),
+        None => println!("  source:  {}", source),
+    }
+}
+
+pub fn show(conn: &Connection, champ_id: u64, game_mode: Option<&str>) -> Result<()> {
+    let rows = stored_rows(conn, Some(champ_id), game_mode)?;
+    if rows.is_empty() {
+        return Err(anyhow!("no stored page for {}", CHAMPIONS[&champ_id]));
+    }
+    for row in &rows {
+        println!("{}", describe(row));
+        print_page(&row.page, row.spells);
+    }
+    Ok(())
+}
+
+pub fn delete(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut deleted = 0;
+    for table in ["rune_pages", "rune_page_history"] {
+        let action = if dry_run { "select count(*)" } else { "delete" };
+        let sql = format!(
+            "{} from {} where champ_id = ?1 and (?2 is null or game_mode = ?2)",
+            action, table
+        );
+        let params = params![champ_id as i64, game_mode];
+        deleted += if dry_run {
+            conn.query_row(&sql, params, |row| row.get::<_, usize>(0))?
+        } else {
+            conn.execute(&sql, params)?
+        };
+    }
+    if deleted == 0 {
+        return Err(anyhow!("no stored page for {}", CHAMPIONS[&champ_id]));
+    }
+    let verb = if dry_run { "would delete" } else { "deleted" };
+    println!("{} {} pages for {}", verb, deleted, CHAMPIONS[&champ_id]);
+    Ok(())
+}
+
+/// Show what every source would offer for a champion, before deduplication or trimming. With
+/// `explain`, show how the pages were ranked and which ones would be dropped instead.
+pub fn sources(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+    position: Option<&str>,
+    opponents: &sources::Opponents,
+    explain: bool,
+) -> Result<()> {
+    // Sources that need the client are skipped when it isn't running
+    let lcuclient = LCUClient::try_new().ok();
+    let patch = lcuclient
+        .as_ref()
+        .and_then(|lcuclient| get_patch(lcuclient).ok());
+    let context = sources::Context {
+        lcuclient: lcuclient.as_ref(),
+        conn,
+        champ_id,
+        game_mode,
+        position,
+        patch: patch.as_deref(),
+        opponents,
+    };
+    if explain {
+        print_explanation(&choose_candidates(lcuclient.as_ref(), &context, None));
+        return Ok(());
+    }
+    for source in sources::registry().enabled() {
+        match sources::fetch(source, &context) {
+            Ok(pages) => {
+                println!("{}: {} pages", source.name(), pages.len());
+                for candidate in &pages {
+                    print_candidate(candidate);
+                }
+            }
+            Err(e) => println!("{}: failed, {}", source.name(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Every candidate in ranked order, with its numbers, what went into its score, and whether it
+/// was kept.
+pub fn print_explanation(candidates: &[Candidate]) {
+    let percent = |rate: f64| format!("{:.1}%", rate * 100.0);
+    for (index, candidate) in candidates.iter().enumerate() {
+        let verdict = match &candidate.dropped {
+            None => "kept".to_string(),
+            Some(dropped) => format!("dropped: {}", dropped),
+        };
+        println!(
+            "{}. {}  score {:.2}  {}",
+            index + 1,
+            candidate.page.name,
+            candidate.score,
+            verdict
+        );
+
+        let mut metrics = vec![format!("source {}", candidate.source)];
+        if let Some(role) = candidate.role {
+            metrics.push(format!("role {}", role));
+        }
+        if let Some(vs) = candidate.vs {
+            metrics.push(format!("vs {}", CHAMPIONS.get(&vs).copied().unwrap_or("?")));
+        }
+        if let Some(win_rate) = candidate.win_rate {
+            metrics.push(format!("win rate {}", percent(win_rate)));
+        }
+        if let Some(pick_rate) = candidate.pick_rate {
+            metrics.push(format!("pick rate {}", percent(pick_rate)));
+        }
+        if let Some(personal) = candidate.personal_win_rate {
+            metrics.push(format!("personal win rate {}", percent(personal)));
+        }
+        if let Some(games) = candidate.games {
+            metrics.push(format!("{} games", games));
+        }
+        if candidate.pinned {
+            metrics.push("pinned".to_string());
+        }
+        if candidate.stale {
+            metrics.push("older patch".to_string());
+        }
+        println!("   {}", metrics.join(", "));
+        println!(
+            "   spells {} {}, perks {:?}",
+            spells::name(candidate.spells.0),
+            spells::name(candidate.spells.1),
+            candidate.page.selected_perk_ids
+        );
+        let parts: Vec<String> = candidate
+            .score_parts
+            .iter()
+            .filter(|(_, part)| *part != 0.0)
+            .map(|(signal, part)| format!("{} {:+.2}", signal, part))
+            .collect();
+        if !parts.is_empty() {
+            println!("   score: {}", parts.join(", "));
+        }
+    }
+}
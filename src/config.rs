@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::{champ_id_by_name, sources, spells};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhenFull {
+    /// Delete the user's least recently modified page to make room
+    DeleteOldest,
+    /// Leave the user's pages alone and don't create any
+    Skip,
+}
+
+/// The summoner spell keys, D is the first slot and F the second
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    #[serde(alias = "d")]
+    D,
+    #[serde(alias = "f")]
+    F,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpellRules {
+    /// Use spells in the order they were saved or fetched in
+    pub keep_order: Option<bool>,
+    /// Which key a spell goes on, like `Flash = "F"`. In `spells`, these are added to the
+    /// default `Flash = "F"` rather than replacing it.
+    pub keys: HashMap<String, Key>,
+}
+
+/// How much each signal counts when ranking pages, see `scoring`. A weight of 0 turns a signal
+/// off.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Weights {
+    /// Earlier sources in `sources`, or higher priority ones, rank higher
+    pub source: f64,
+    /// The build provider's win rate
+    pub win_rate: f64,
+    pub pick_rate: f64,
+    /// How many games a win rate is based on
+    pub sample_size: f64,
+    /// The player's own record with the page
+    pub personal_win_rate: f64,
+    /// How recently the player saved or played the page
+    pub recency: f64,
+    /// Days until recency counts half
+    pub recency_half_life_days: f64,
+    pub pinned: f64,
+    /// Builds for the player's role
+    pub role: f64,
+    /// Builds against the lane opponent, and less so against the rest of the enemy team
+    pub matchup: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            source: 1.0,
+            win_rate: 2.0,
+            pick_rate: 0.5,
+            sample_size: 0.5,
+            personal_win_rate: 1.0,
+            recency: 0.5,
+            recency_half_life_days: 14.0,
+            pinned: 3.0,
+            role: 1.0,
+            matchup: 2.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Pages with this in their name belong to us, and get deleted when we need room. It has to be
+    /// in brackets, so it can't turn up in the player's own page names by accident.
+    pub marker: String,
+    /// Name for stored pages, `{label}` is "saved", "pinned" and so on
+    pub saved_page_name: String,
+    /// Name for pages from a build provider
+    pub provider_page_name: String,
+    /// How long build provider responses are used before asking again. Expired responses are
+    /// still used when the provider can't be reached.
+    pub mobalytics_cache_secs: u64,
+    /// How long to wait between looking for the client
+    pub discovery_interval_secs: u64,
+    /// Where pages come from, in order of preference. Defaults to every source, by priority.
+    pub sources: Option<Vec<String>>,
+    pub when_full: WhenFull,
+    /// Serve the HTTP API on this port, on localhost only
+    pub api_port: Option<u16>,
+    /// Web pages that may use the API, like `http://localhost:3000` for a stream overlay. Other
+    /// web pages are turned away.
+    pub api_origins: Vec<String>,
+    /// Directory of JSON and TOML build files for the folder source
+    pub builds_dir: Option<PathBuf>,
+    /// How many recent games the history source looks through
+    pub match_history_games: usize,
+    /// Take `!rp` commands in champ select chat. Replies go to the log.
+    pub chat_commands: bool,
+    /// Post the page list and replies in champ select chat too. The client may show these to
+    /// the whole team.
+    pub chat_replies: bool,
+    /// Fetch provider builds for likely picks ahead of time
+    pub prefetch: bool,
+    /// How many of the player's champions to prefetch in the lobby, most recently saved first
+    pub prefetch_pool: usize,
+    pub weights: Weights,
+    /// Provider builds from fewer games are left out
+    pub min_games: u32,
+    /// Provider builds picked less often than this are left out, as a fraction like 0.01
+    pub min_pick_rate: f64,
+    pub spells: SpellRules,
+    /// Per champion spell rules, these win over `spells`
+    pub champion_spells: HashMap<String, SpellRules>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            marker: "(RP)".to_string(),
+            saved_page_name: "{champion} ({label}) {marker}".to_string(),
+            provider_page_name: "{build} ({source}) {marker}".to_string(),
+            mobalytics_cache_secs: 64800,
+            discovery_interval_secs: 10,
+            sources: None,
+            when_full: WhenFull::DeleteOldest,
+            api_port: None,
+            api_origins: Vec::new(),
+            builds_dir: None,
+            match_history_games: 20,
+            chat_commands: false,
+            chat_replies: false,
+            prefetch: true,
+            prefetch_pool: 10,
+            weights: Weights::default(),
+            min_games: 50,
+            min_pick_rate: 0.01,
+            spells: SpellRules {
+                keep_order: None,
+                keys: HashMap::from([("Flash".to_string(), Key::F)]),
+            },
+            champion_spells: HashMap::new(),
+        }
+    }
+}
+
+/// Fill in `{name}` placeholders in a page name template.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |name, (key, value)| {
+            name.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+fn check_template(setting: &str, template: &str, allowed: &[&str]) -> Result<()> {
+    if !template.contains("{marker}") {
+        return Err(anyhow!(
+            "{} must contain {{marker}}, or old pages can't be cleaned up",
+            setting
+        ));
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("{} has an unclosed {{", setting))?;
+        let key = &rest[start + 1..start + end];
+        if key != "marker" && !allowed.contains(&key) {
+            return Err(anyhow!(
+                "{} has unknown placeholder {{{}}}, use one of {{{}}}",
+                setting,
+                key,
+                allowed.join("}, {")
+            ));
+        }
+        rest = &rest[start + end..];
+    }
+    Ok(())
+}
+
+fn check_spell_rules(setting: &str, rules: &SpellRules) -> Result<()> {
+    for spell in rules.keys.keys() {
+        if spells::id_by_name(spell).is_none() {
+            return Err(anyhow!(
+                "{} has unknown spell {:?}, use one of {}",
+                setting,
+                spell,
+                spells::names().join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Put back the default spell keys for spells the config doesn't set a key for.
+    fn add_default_spell_keys(&mut self) {
+        for (spell, key) in Config::default().spells.keys {
+            let id = spells::id_by_name(&spell);
+            if !self
+                .spells
+                .keys
+                .keys()
+                .any(|name| spells::id_by_name(name) == id)
+            {
+                self.spells.keys.insert(spell, key);
+            }
+        }
+    }
+
+    /// A builds_dir that isn't there only turns off the folder source, it may be on a drive
+    /// that isn't mounted yet.
+    fn check_builds_dir(&mut self) {
+        if let Some(dir) = &self.builds_dir {
+            if !dir.is_dir() {
+                println!(
+                    "builds_dir {} isn't a directory, not using the folder source",
+                    dir.display()
+                );
+                self.builds_dir = None;
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let bracketed = matches!(
+            (self.marker.chars().next(), self.marker.chars().last()),
+            (Some('('), Some(')')) | (Some('['), Some(']')) | (Some('<'), Some('>'))
+        );
+        if self.marker.chars().count() < 3 || !bracketed {
+            return Err(anyhow!(
+                "marker must be something in brackets like \"(RP)\", pages with it in their \
+                    name get deleted"
+            ));
+        }
+        check_template(
+            "saved_page_name",
+            &self.saved_page_name,
+            &["champion", "label"],
+        )?;
+        check_template(
+            "provider_page_name",
+            &self.provider_page_name,
+            &["build", "source"],
+        )?;
+        if self.discovery_interval_secs == 0 {
+            return Err(anyhow!("discovery_interval_secs must be at least 1"));
+        }
+        let known = sources::registry().names();
+        for source in self.sources.iter().flatten() {
+            if !known.contains(&source.as_str()) {
+                return Err(anyhow!(
+                    "unknown source {:?}, use one of {:?}",
+                    source,
+                    known
+                ));
+            }
+        }
+        let weights = &self.weights;
+        if [
+            weights.source,
+            weights.win_rate,
+            weights.pick_rate,
+            weights.sample_size,
+            weights.personal_win_rate,
+            weights.recency,
+            weights.pinned,
+            weights.role,
+            weights.matchup,
+        ]
+        .iter()
+        .any(|weight| !weight.is_finite())
+        {
+            return Err(anyhow!("weights must be numbers"));
+        }
+        if weights.recency_half_life_days <= 0.0 {
+            return Err(anyhow!(
+                "weights.recency_half_life_days must be more than 0"
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.min_pick_rate) {
+            return Err(anyhow!("min_pick_rate must be between 0 and 1"));
+        }
+        if self.api_port == Some(0) {
+            return Err(anyhow!("api_port can't be 0"));
+        }
+        check_spell_rules("spells", &self.spells)?;
+        for (champion, rules) in &self.champion_spells {
+            if champ_id_by_name(champion).is_none() {
+                return Err(anyhow!(
+                    "champion_spells has unknown champion {:?}",
+                    champion
+                ));
+            }
+            check_spell_rules(&format!("champion_spells.{}", champion), rules)?;
+        }
+        Ok(())
+    }
+
+    pub fn saved_page_name(&self, champion: &str, label: &str) -> String {
+        fill(
+            &self.saved_page_name,
+            &[
+                ("champion", champion),
+                ("label", label),
+                ("marker", &self.marker),
+            ],
+        )
+    }
+
+    pub fn provider_page_name(&self, build: &str, source: &str) -> String {
+        fill(
+            &self.provider_page_name,
+            &[
+                ("build", build),
+                ("source", source),
+                ("marker", &self.marker),
+            ],
+        )
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rune_pager").join("config.toml"))
+}
+
+/// The API token is kept next to the config file, readable only by the player.
+pub fn api_token_path(config_path: Option<&Path>) -> Option<PathBuf> {
+    let path = config_path.map(Path::to_path_buf).or_else(default_path)?;
+    Some(path.parent()?.join("api_token"))
+}
+
+/// Load and validate the config file. A missing file at the default location just means the
+/// defaults are used, but a file that was asked for has to exist.
+pub fn load(path: Option<&Path>) -> Result<()> {
+    let (path, required) = match path {
+        Some(path) => (Some(path.to_path_buf()), true),
+        None => (default_path(), false),
+    };
+    let config = match path {
+        Some(path) if required || path.exists() => {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config {}", path.display()))?;
+            let mut config: Config = toml::from_str(&contents)
+                .with_context(|| format!("invalid config {}", path.display()))?;
+            config.add_default_spell_keys();
+            config
+                .validate()
+                .with_context(|| format!("invalid config {}", path.display()))?;
+            config.check_builds_dir();
+            println!("loaded config from {}", path.display());
+            config
+        }
+        _ => Config::default(),
+    };
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("config was already loaded"))
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
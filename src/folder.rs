@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Context as _, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::config::config;
+use crate::sources::{self, BuildSource, Context};
+use crate::{champ_id_by_name, spells, Candidate, RunePage, CHAMPIONS};
+
+/// One build in a build file. Files hold either a single build, or a list of them under `builds`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileBuild {
+    champion: String,
+    /// Only used for the player's role, e.g. "middle" or "jungle"
+    #[serde(default)]
+    role: Option<String>,
+    /// Only used in this game mode, e.g. "ARAM"
+    #[serde(default)]
+    mode: Option<String>,
+    /// Only used against this enemy champion
+    #[serde(default)]
+    vs: Option<String>,
+    label: String,
+    primary_style: i64,
+    sub_style: i64,
+    perks: Vec<i64>,
+    /// Names like "Flash" or spell ids
+    spells: [String; 2],
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BuildList {
+    builds: Vec<FileBuild>,
+}
+
+fn read_builds(path: &Path) -> Result<Vec<FileBuild>> {
+    let contents = fs::read_to_string(path)?;
+    let file: Value = match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => toml::from_str(&contents)?,
+        _ => serde_json::from_str(&contents)?,
+    };
+    if file.get("builds").is_some() {
+        Ok(serde_json::from_value::<BuildList>(file)?.builds)
+    } else {
+        Ok(vec![serde_json::from_value(file)?])
+    }
+}
+
+fn to_candidate(build: FileBuild, vs: Option<u64>) -> Result<Candidate> {
+    let spell =
+        |name: &str| spells::id_by_name(name).ok_or_else(|| anyhow!("unknown spell {}", name));
+    let role = match &build.role {
+        Some(role) => {
+            Some(sources::normalize_role(role).ok_or_else(|| anyhow!("unknown role {}", role))?)
+        }
+        None => None,
+    };
+    let mut label = match &build.role {
+        Some(role) => format!("{} {}", build.label, role),
+        None => build.label.clone(),
+    };
+    if let Some(vs) = vs {
+        label = format!("{} vs {}", label, CHAMPIONS[&vs]);
+    }
+    let page = RunePage {
+        name: config().provider_page_name(&label, "folder"),
+        primary_style_id: build.primary_style,
+        sub_style_id: build.sub_style,
+        selected_perk_ids: build.perks,
+        ..Default::default()
+    };
+    let spells = (spell(&build.spells[0])?, spell(&build.spells[1])?);
+    Ok(Candidate {
+        role,
+        vs,
+        ..Candidate::new(page, spells, "folder")
+    })
+}
+
+/// JSON and TOML build files in `builds_dir`, for sharing vetted builds through a git repo or a
+/// shared drive. A broken file is logged and skipped, so one bad file doesn't hide the rest.
+pub struct Folder;
+
+impl BuildSource for Folder {
+    fn name(&self) -> &str {
+        "folder"
+    }
+
+    fn priority(&self) -> i32 {
+        75
+    }
+
+    fn has_matchups(&self) -> bool {
+        true
+    }
+
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>> {
+        let Some(dir) = &config().builds_dir else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| {
+                    ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("toml")
+                })
+            })
+            .collect();
+        // Files are read in name order, so their names decide the order
+        paths.sort();
+
+        let mut candidates = Vec::new();
+        for path in paths {
+            let builds = match read_builds(&path) {
+                Ok(builds) => builds,
+                Err(e) => {
+                    log!("skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for build in builds {
+                if champ_id_by_name(&build.champion) != Some(context.champ_id) {
+                    continue;
+                }
+                if build
+                    .mode
+                    .as_ref()
+                    .is_some_and(|mode| !mode.eq_ignore_ascii_case(context.game_mode))
+                {
+                    continue;
+                }
+                let vs = match &build.vs {
+                    Some(name) => match champ_id_by_name(name) {
+                        Some(vs) => Some(vs),
+                        None => {
+                            log!(
+                                "skipping a build in {}: unknown champion {}",
+                                path.display(),
+                                name
+                            );
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                // Matchup builds only make sense once that champion shows up
+                if vs.is_some_and(|vs| !context.opponents.enemies.contains(&vs)) {
+                    continue;
+                }
+                match to_candidate(build, vs) {
+                    Ok(candidate) => candidates.push(candidate),
+                    Err(e) => log!("skipping a build in {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(candidates)
+    }
+}
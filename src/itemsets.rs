@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use crate::config::config;
+use crate::get_mobalytics_builds;
+use crate::lcu::LCUClient;
+
+pub fn setup_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists item_sets (
+                champ_id integer not null,
+                game_mode text not null,
+                sets text not null,
+                primary key (champ_id, game_mode) on conflict replace
+            )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn sets_uri(lcuclient: &LCUClient) -> Result<String> {
+    let summoner = lcuclient.get("/lol-summoner/v1/current-summoner")?.text()?;
+    let summoner: Value = serde_json::from_str(&summoner)?;
+    let summoner_id = summoner["summonerId"]
+        .as_u64()
+        .context("no summonerId for current summoner")?;
+    Ok(format!("/lol-item-sets/v1/item-sets/{}/sets", summoner_id))
+}
+
+fn is_for_champ(set: &Value, champ_id: u64) -> bool {
+    set["associatedChampions"]
+        .as_array()
+        .is_some_and(|champs| champs.iter().any(|c| c.as_u64() == Some(champ_id)))
+}
+
+fn is_ours(set: &Value) -> bool {
+    set["title"]
+        .as_str()
+        .is_some_and(|title| title.contains(&config().marker))
+}
+
+/// Store the player's own item sets for this champion. Sets we made ourselves are skipped, and
+/// nothing is stored if the player has no sets for the champion.
+pub fn save(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+) -> Result<()> {
+    let all = lcuclient.get(&sets_uri(lcuclient)?)?.text()?;
+    let all: Value = serde_json::from_str(&all)?;
+    let sets: Vec<&Value> = all["itemSets"]
+        .as_array()
+        .context("no itemSets in response")?
+        .iter()
+        .filter(|set| is_for_champ(set, champ_id) && !is_ours(set))
+        .collect();
+    if sets.is_empty() {
+        return Ok(());
+    }
+    log!("saving {} item sets", sets.len());
+    conn.execute(
+        "insert into item_sets (champ_id, game_mode, sets) values (?1, ?2, ?3)",
+        params![champ_id as i64, game_mode, serde_json::to_string(&sets)?],
+    )?;
+    Ok(())
+}
+
+fn get_stored_sets(conn: &Connection, champ_id: u64, game_mode: &str) -> Result<Vec<Value>> {
+    let sets: Option<String> = conn
+        .query_row(
+            "select sets from item_sets where champ_id = ?1
+                order by game_mode = ?2 desc limit 1",
+            params![champ_id as i64, game_mode],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match sets {
+        Some(sets) => Ok(serde_json::from_str(&sets)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Mobalytics has item groups as `[{"type": ..., "items": [...]}]`, turn those into item set
+/// blocks. Anything that doesn't look like that is an error, so a change on their end shows up
+/// in the log instead of as missing item sets.
+fn moba_blocks(items: &Value) -> Result<Vec<Value>> {
+    let groups = items
+        .as_array()
+        .context("items isn't a list of item groups")?;
+    groups
+        .iter()
+        .map(|group| {
+            let kind = group["type"]
+                .as_str()
+                .or(group["name"].as_str())
+                .context("item group without a type or name")?;
+            let items = group["items"]
+                .as_array()
+                .context("item group without items")?
+                .iter()
+                .map(|item| match item {
+                    Value::String(id) => Ok(json!({ "id": id, "count": 1 })),
+                    Value::Number(id) => Ok(json!({ "id": id.to_string(), "count": 1 })),
+                    _ => Err(anyhow!("item {} isn't an id", item)),
+                })
+                .collect::<Result<Vec<Value>>>()?;
+            Ok(json!({ "type": kind, "items": items }))
+        })
+        .collect()
+}
+
+fn mobalytics_sets(conn: &Connection, champ_id: u64) -> Vec<Value> {
+    let builds = match get_mobalytics_builds(conn, champ_id) {
+        Ok(builds) => builds,
+        Err(e) => {
+            log!("couldn't get mobalytics item sets: {}", e);
+            return Vec::new();
+        }
+    };
+    builds
+        .iter()
+        .filter_map(|build| {
+            let blocks = match moba_blocks(&build.items) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    log!("couldn't read mobalytics items for {}: {}", build.name, e);
+                    return None;
+                }
+            };
+            if blocks.is_empty() {
+                return None;
+            }
+            Some(json!({
+                "associatedChampions": [champ_id],
+                "associatedMaps": [],
+                "blocks": blocks,
+                "map": "any",
+                "mode": "any",
+                "preferredItemSlots": [],
+                "sortrank": 0,
+                "startedFrom": "blank",
+                "title": config().provider_page_name(&build.name, "mobalytics"),
+                "type": "custom",
+                "uid": format!("rune-pager-{}-{}", champ_id, build.name),
+            }))
+        })
+        .collect()
+}
+
+/// Replace the item sets we added last time with the stored and Mobalytics sets for this
+/// champion.
+pub fn apply(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+) -> Result<()> {
+    let uri = sets_uri(lcuclient)?;
+    let all = lcuclient.get(&uri)?.text()?;
+    let mut all: Value = serde_json::from_str(&all)?;
+    let sets = all["itemSets"]
+        .as_array_mut()
+        .context("no itemSets in response")?;
+    sets.retain(|set| !is_ours(set));
+
+    // The player's own sets are usually still there, only put back the ones that were deleted
+    let mut added = 0;
+    for set in get_stored_sets(conn, champ_id, game_mode)? {
+        if !sets.iter().any(|existing| existing["uid"] == set["uid"]) {
+            sets.push(set);
+            added += 1;
+        }
+    }
+    for set in mobalytics_sets(conn, champ_id) {
+        sets.push(set);
+        added += 1;
+    }
+    log!("adding {} item sets", added);
+    let put = lcuclient.put(&uri, serde_json::to_string(&all)?)?;
+    if !put.status().is_success() {
+        log!("{:?}", put.text()?);
+    }
+    Ok(())
+}
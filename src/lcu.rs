@@ -1,4 +1,5 @@
 use std::cmp::min;
+use std::io::ErrorKind;
 use std::thread::sleep;
 use std::time::Duration;
 use base64::prelude::*;
@@ -12,6 +13,7 @@ use std::collections::HashMap;
 use std::net::TcpStream;
 use std::process::Command;
 use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
 use anyhow::Result;
 
 static LCUCERT: &[u8; 1492] = b"-----BEGIN CERTIFICATE-----
@@ -69,7 +71,7 @@ fn get_lcu_info() -> Result<(u16, String), String> {
         .ok_or_else(|| "No match for token".to_string())?;
     let port: u16 = port_caps.get(1).unwrap().as_str().parse().unwrap();
     let token = token_caps.get(1).unwrap().as_str().to_string();
-    println!("LCU: port={} token={}", port, token);
+    log!("LCU: port={} token={}", port, token);
     Ok((port, token))
 }
 
@@ -95,7 +97,7 @@ fn build_lcu_client(port: u16, token: String) -> Result<LCUClient> {
         CONTENT_TYPE,
         "application/json".parse().unwrap(),
     );
-    println!("{} {:?}", port, token);
+    log!("{} {:?}", port, token);
     let reqclient = reqwest::blocking::Client::builder()
         .add_root_certificate(cert)
         .default_headers(headers)
@@ -103,16 +105,32 @@ fn build_lcu_client(port: u16, token: String) -> Result<LCUClient> {
     Ok(LCUClient { reqclient, port })
 }
 
+static DISCOVERY_INTERVAL: AtomicU64 = AtomicU64::new(10);
+
+/// How many seconds to wait between looks for the client.
+pub fn set_discovery_interval(secs: u64) {
+    DISCOVERY_INTERVAL.store(secs, Ordering::Relaxed);
+}
+
+fn wait_for_lcu_info() -> (u16, String) {
+    let mut result = get_lcu_info();
+    while result.is_err() {
+        log!("LCU not found, sleeping...");
+        sleep(Duration::from_secs(DISCOVERY_INTERVAL.load(Ordering::Relaxed)));
+        result = get_lcu_info();
+    }
+    result.unwrap()
+}
+
 impl LCUClient {
     pub fn new() -> Result<Self> {
-        let mut result = get_lcu_info();
-        while result.is_err() {
-            println!("LCU not found, sleeping...");
-            let ten_sec = Duration::from_secs(10);
-            sleep(ten_sec);
-            result = get_lcu_info();
-        }
-        let (port, token) = result.unwrap();
+        let (port, token) = wait_for_lcu_info();
+        build_lcu_client(port, token)
+    }
+
+    /// Like `new`, but fails right away if the client isn't running.
+    pub fn try_new() -> Result<Self> {
+        let (port, token) = get_lcu_info().map_err(anyhow::Error::msg)?;
         build_lcu_client(port, token)
     }
 
@@ -138,6 +156,9 @@ impl LCUClient {
     }
 }
 
+/// How long `dispatch` waits for an event.
+const DISPATCH_TIMEOUT: Duration = Duration::from_millis(250);
+
 type BoxedCallback = Box<dyn FnMut(&serde_json::Value) -> Result<()>>;
 struct Subscriber {
     callback: BoxedCallback,
@@ -153,14 +174,7 @@ pub struct LCUWebSocket {
 impl LCUWebSocket {
 
     pub fn new() -> Self {
-        let mut result = get_lcu_info();
-        while result.is_err() {
-            println!("LCU not found, sleeping...");
-            let ten_sec = Duration::from_secs(10);
-            sleep(ten_sec);
-            result = get_lcu_info();
-        }
-        let (port, token) = result.unwrap();
+        let (port, token) = wait_for_lcu_info();
 
         let cert = Certificate::from_pem(LCUCERT).unwrap();
 
@@ -173,13 +187,15 @@ impl LCUWebSocket {
         let stream = TcpStream::connect(addr).unwrap();
         let stream = connector.connect("127.0.0.1", stream).unwrap();
 
-        println!("got connection!");
+        log!("got connection!");
 
         let mut request = "wss://127.0.0.1".into_client_request().unwrap();
         request.headers_mut().insert(USER_AGENT, "LCU crate by DocWilco".parse().unwrap());
         let b64 = BASE64_STANDARD.encode(format!("riot:{}", token));
         request.headers_mut().insert(AUTHORIZATION, format!("Basic {}", b64).parse().unwrap());
         let (ws, _) = tungstenite::client(request, stream).unwrap();
+        // Don't wait on events forever, so the caller gets to do other work in between
+        ws.get_ref().get_ref().set_read_timeout(Some(DISPATCH_TIMEOUT)).unwrap();
         LCUWebSocket{ws, subscribers: HashMap::new(), next_id: 0}
     }
 
@@ -206,12 +222,18 @@ impl LCUWebSocket {
         Err("handler id not found".to_string())
     }
 
+    /// Handle the next event, or return after `DISPATCH_TIMEOUT` if there isn't one.
     pub fn dispatch(&mut self) -> Result<(), String> {
-        //println!("dispatch");
+        //log!("dispatch");
         let message = self.ws.read();
+        if let Err(tungstenite::Error::Io(e)) = &message {
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                return Ok(());
+            }
+        }
         if let Ok(tungstenite::protocol::Message::Text(message)) = message {
             if message.is_empty() {
-                println!("empty message");
+                log!("empty message");
                 return Ok(());
             }
             let message: serde_json::Value = serde_json::from_str(&message).unwrap();
@@ -228,7 +250,7 @@ impl LCUWebSocket {
             Ok(())
         } else {
             let debug = format!("{:?}", message);
-            println!("{:?}", &debug[..min(60, debug.len())]);
+            log!("{:?}", &debug[..min(60, debug.len())]);
             Err("receiving failed".to_string())
         }
     }
@@ -1,21 +1,242 @@
 use anyhow::{anyhow, Context, Result};
-use cached::proc_macro::cached;
+use clap::{Parser, Subcommand};
+use config::config;
 use lcu::{LCUClient, LCUWebSocket};
-use rusqlite::{params, Connection, Row};
+use rusqlite::{params, Connection, OptionalExtension, Row};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::fmt;
+use std::path::PathBuf;
 use std::str;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Once;
 use std::thread;
-use std::time::{self, Duration};
+use std::time::{self, SystemTime, UNIX_EPOCH};
 
+/* Like println!, but goes to the dashboard's log pane while that's showing */
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::status::log(format!($($arg)*))
+    };
+}
+
+mod api;
+mod cache;
+mod chat;
+mod commands;
+mod config;
+mod folder;
+mod itemsets;
 mod lcu;
+mod match_history;
+mod matchups;
+mod perks;
+mod prefetch;
+mod scoring;
+mod share;
+mod snapshot;
+mod sources;
+mod spells;
+mod status;
+mod transfer;
+#[cfg(feature = "tui")]
+mod tui;
 
-static MARKER: &str = "(RP)";
+static PIN_TAG: &str = "[pin]";
 
 /* this provides static CHAMPIONS phf::Map<u64, &str> */
 include!(concat!(env!("OUT_DIR"), "/champions.rs"));
 
+#[derive(Parser, Debug)]
+#[command(version, about = "Some rune page management for LoL")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print what would be changed instead of changing it. Commands that can't do this refuse
+    /// the flag.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Config file to use instead of the default location
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Watch the client and set up pages during champ select (the default)
+    Run {
+        /// Show a dashboard instead of the log (needs the tui feature)
+        #[arg(long)]
+        tui: bool,
+    },
+    /// List stored pages
+    List {
+        /// Only list pages for this champion
+        champion: Option<String>,
+    },
+    /// Show the stored pages for a champion
+    Show {
+        champion: String,
+        /// Only show the page for this game mode
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Delete the stored pages for a champion
+    Delete {
+        champion: String,
+        /// Only delete the page for this game mode
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Create the pages for a champion in the client right now
+    Apply {
+        champion: String,
+        /// Game mode to look up stored pages for
+        #[arg(long, default_value = "CLASSIC")]
+        mode: String,
+        /// Prefer builds for this role, e.g. mid or support
+        #[arg(long, value_parser = role_arg)]
+        role: Option<&'static str>,
+        /// Prefer builds against this lane opponent
+        #[arg(long)]
+        vs: Option<String>,
+        /// Show how each page was scored, and why pages were dropped
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Show what every source would offer for a champion
+    Sources {
+        champion: String,
+        /// Game mode to look up stored pages for
+        #[arg(long, default_value = "CLASSIC")]
+        mode: String,
+        /// Prefer builds for this role, e.g. mid or support
+        #[arg(long, value_parser = role_arg)]
+        role: Option<&'static str>,
+        /// Prefer builds against this lane opponent
+        #[arg(long)]
+        vs: Option<String>,
+        /// Show how each page was scored, and why pages were dropped
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Write every stored page to a JSON or TOML file
+    Export {
+        file: PathBuf,
+        /// File format, guessed from the file extension if not given
+        #[arg(long, value_enum)]
+        format: Option<transfer::Format>,
+    },
+    /// Merge stored pages from a JSON or TOML file into the database
+    Import {
+        file: PathBuf,
+        /// File format, guessed from the file extension if not given
+        #[arg(long, value_enum)]
+        format: Option<transfer::Format>,
+        /// What to do when a page for the same champion and mode is already stored
+        #[arg(long, value_enum, default_value_t = transfer::Conflict::Keep)]
+        on_conflict: transfer::Conflict,
+    },
+    /// Print a share code for a stored page
+    Encode {
+        champion: String,
+        /// Game mode of the stored page, any mode if not given
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Store a page from a share code, or push it to the client
+    Decode {
+        code: String,
+        /// Game mode to store the page under
+        #[arg(long, default_value = "CLASSIC")]
+        mode: String,
+        /// Create the page in the client instead of storing it
+        #[arg(long)]
+        client: bool,
+    },
+    /// Save every rune page currently in the client
+    Snapshot {
+        /// Optional name to restore the snapshot by
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List saved snapshots
+    Snapshots,
+    /// Inspect or clear the cache of build provider responses
+    Cache {
+        #[command(subcommand)]
+        command: cache::CacheCommand,
+    },
+    /// Pin stored pages so auto-save leaves them alone
+    Pin {
+        champion: String,
+        /// Only pin the page for this game mode
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Check stored pages against the client's current runes
+    VerifyDb {
+        /// Write repaired pages back to the database
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Let auto-save replace stored pages again
+    Unpin {
+        champion: String,
+        /// Only unpin the page for this game mode
+        #[arg(long)]
+        mode: Option<String>,
+    },
+    /// Replace the client's rune pages with a saved snapshot
+    Restore {
+        /// Snapshot id or name
+        snapshot: String,
+    },
+    /// Save pages from recent games for champions that have nothing stored
+    Backfill {
+        /// How many recent games to go through
+        #[arg(long, default_value_t = 20)]
+        games: usize,
+    },
+}
+
+/// Lowercase ASCII letters only, so "Kai'Sa", "kaisa" and "KAI SA" all match.
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Like `champ_id_by_name`, but with an error for command line arguments.
+fn champ_arg(name: &str) -> Result<u64> {
+    champ_id_by_name(name).ok_or_else(|| anyhow!("unknown champion: {}", name))
+}
+
+fn role_arg(role: &str) -> Result<&'static str, String> {
+    sources::normalize_role(role).ok_or_else(|| format!("unknown role: {}", role))
+}
+
+/// A lane opponent given on the command line, who is then the only visible enemy.
+fn opponents_arg(vs: Option<&str>) -> Result<sources::Opponents> {
+    let vs = vs.map(champ_arg).transpose()?;
+    Ok(sources::Opponents {
+        enemies: vs.into_iter().collect(),
+        laner: vs,
+    })
+}
+
+fn champ_id_by_name(name: &str) -> Option<u64> {
+    if let Ok(id) = name.parse::<u64>() {
+        return CHAMPIONS.contains_key(&id).then_some(id);
+    }
+    let name = normalize_name(name);
+    CHAMPIONS
+        .entries()
+        .find(|(_, champ)| normalize_name(champ) == name)
+        .map(|(id, _)| *id)
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 struct RunePage {
@@ -34,6 +255,16 @@ struct RunePage {
     sub_style_id: i64,
 }
 
+impl RunePage {
+    /// Same styles and perks, whatever the name. Perks are compared in order, pages checked by
+    /// `perks` have them in slot order.
+    fn same_runes(&self, other: &RunePage) -> bool {
+        self.primary_style_id == other.primary_style_id
+            && self.sub_style_id == other.sub_style_id
+            && self.selected_perk_ids == other.selected_perk_ids
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct RerollPoints {
@@ -72,78 +303,29 @@ struct RiotSummoner<'a> {
     summoner_level: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct MatchHistoryResult<'a> {
-    #[serde(borrow)]
-    matches: Vec<MatchHistoryMatch<'a>>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct MatchHistoryMatch<'a> {
-    game_id: u64,
-    platform_id: &'a str,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Participant {
-    participant_id: u64,
-    spell1_id: u64,
-    spell2_id: u64,
-    stats: ParticipantStats,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct ParticipantStats {
-    perk0: i64,
-    perk1: i64,
-    perk2: i64,
-    perk3: i64,
-    perk4: i64,
-    perk5: i64,
-    stat_perk0: i64,
-    stat_perk1: i64,
-    stat_perk2: i64,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Player<'a> {
-    platform_id: &'a str,
-    account_id: &'a str,
-    summoner_name: &'a str,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct ParticipantIdentity<'a> {
-    participant_id: u64,
-    #[serde(borrow)]
-    player: Player<'a>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct MatchDetails<'a> {
-    participants: Vec<Participant>,
-    #[serde(borrow)]
-    participant_identities: Vec<ParticipantIdentity<'a>>,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct SelectSession {
     local_player_cell_id: u64,
     my_team: Vec<SessionPlayer>,
     their_team: Vec<SessionPlayer>,
+    /// Champions anyone can swap for in ARAM
+    #[serde(default)]
+    bench_champions: Vec<BenchChampion>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BenchChampion {
+    champion_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 struct SessionPlayer {
+    /// Empty in modes without roles
+    #[serde(default)]
+    assigned_position: String,
     cell_id: u64,
     champion_id: u64,
     champion_pick_intent: u64,
@@ -167,7 +349,32 @@ enum GamePhase {
 
 fn setup_sqlite() -> Result<Connection> {
     let conn = Connection::open("rune_pages.db").context("failed to open DB file")?;
+    setup_rune_pages_table(&conn)?;
+    add_column(&conn, "rune_pages", "patch text")?;
+    add_column(&conn, "rune_pages", "pinned integer not null default 0")?;
+    add_column(&conn, "rune_pages", "saved_at integer")?;
+    conn.execute(
+        "create table if not exists rune_page_history (
+                champ_id integer not null,
+                game_mode text not null,
+                spell1_id integer not null,
+                spell2_id integer not null,
+                page text not null,
+                patch text,
+                primary key (champ_id, game_mode) on conflict replace
+            )",
+        [],
+    )?;
+    add_column(&conn, "rune_page_history", "saved_at integer")?;
+    snapshot::setup_table(&conn)?;
+    itemsets::setup_table(&conn)?;
+    matchups::setup_table(&conn)?;
+    cache::setup_table(&conn)?;
+    match_history::setup_table(&conn)?;
+    Ok(conn)
+}
 
+fn setup_rune_pages_table(conn: &Connection) -> Result<()> {
     let create = conn.execute(
         "create table rune_pages (
                 champ_id integer not null,
@@ -181,7 +388,7 @@ fn setup_sqlite() -> Result<Connection> {
     );
     // If create worked, we're done
     if create.is_ok() {
-        return Ok(conn);
+        return Ok(());
     }
     // If create didn't work, we can have either v1 or v2. Try to add a v2 column.
     let alter = conn.execute(
@@ -190,7 +397,7 @@ fn setup_sqlite() -> Result<Connection> {
     );
     // If add column failed, we're on v2, so we're done.
     if alter.is_err() {
-        return Ok(conn);
+        return Ok(());
     }
     // Add column succeeded, so convert v1 to v2
     // see https://www.sqlite.org/lang_altertable.html for specifics on order of operations.
@@ -220,7 +427,24 @@ fn setup_sqlite() -> Result<Connection> {
     conn.execute("drop table rune_pages", [])?;
     conn.execute("alter table rune_pages_new rename to rune_pages", [])?;
     println!("upgraded database");
-    Ok(conn)
+    Ok(())
+}
+
+/// Add a column to an existing table, unless it's already there.
+fn add_column(conn: &Connection, table: &str, column: &str) -> Result<()> {
+    match conn.execute(&format!("alter table {} add column {}", table, column), []) {
+        Ok(_) => {
+            println!("added {} to {}", column, table);
+            Ok(())
+        }
+        Err(e) if e.to_string().contains("duplicate column") => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Seconds since the epoch, for `saved_at` columns.
+fn unix_time() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
 }
 
 fn save_rune_page(
@@ -229,32 +453,175 @@ fn save_rune_page(
     spells: (u64, u64),
     game_mode: &str,
     rune_page: &RunePage,
+    patch: Option<&str>,
 ) -> Result<()> {
     let rune_page_json = serde_json::to_string(&rune_page)?;
     conn.execute(
-        "INSERT INTO rune_pages (champ_id, game_mode, spell1_id, spell2_id, page)
-                  VALUES (?1, ?2, ?3, ?4, ?5)",
+        // Not a plain replace, that would reset the pinned flag of a page being overwritten
+        "INSERT INTO rune_pages (champ_id, game_mode, spell1_id, spell2_id, page, patch, saved_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                  ON CONFLICT (champ_id, game_mode) DO UPDATE SET
+                    spell1_id = excluded.spell1_id,
+                    spell2_id = excluded.spell2_id,
+                    page = excluded.page,
+                    patch = excluded.patch,
+                    saved_at = excluded.saved_at",
         params![
             champ_id as i64,
             game_mode,
             spells.0 as i64,
             spells.1 as i64,
-            rune_page_json
+            rune_page_json,
+            patch,
+            unix_time()?
         ],
     )?;
     Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Save the page played at game start, unless the stored page is pinned. A pinned page is left
+/// alone, and the played page goes into the history table instead. Pages with `PIN_TAG` in their
+/// name get pinned right away.
+fn autosave_rune_page(
+    conn: &Connection,
+    champ_id: u64,
+    spells: (u64, u64),
+    game_mode: &str,
+    rune_page: &RunePage,
+    patch: Option<&str>,
+) -> Result<()> {
+    if rune_page.name.contains(PIN_TAG) {
+        log!("page name contains {}, pinning it", PIN_TAG);
+        save_rune_page(conn, champ_id, spells, game_mode, rune_page, patch)?;
+        set_pinned(conn, champ_id, Some(game_mode), true)?;
+        return Ok(());
+    }
+    let pinned: bool = conn
+        .query_row(
+            "select pinned from rune_pages where champ_id = ?1 and game_mode = ?2",
+            params![champ_id as i64, game_mode],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(false);
+    if !pinned {
+        // Without a pinned page the history would only hold an older copy of the same thing
+        conn.execute(
+            "delete from rune_page_history where champ_id = ?1 and game_mode = ?2",
+            params![champ_id as i64, game_mode],
+        )?;
+        return save_rune_page(conn, champ_id, spells, game_mode, rune_page, patch);
+    }
+    log!("stored page is pinned, saving to history instead");
+    conn.execute(
+        "INSERT INTO rune_page_history
+                (champ_id, game_mode, spell1_id, spell2_id, page, patch, saved_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            champ_id as i64,
+            game_mode,
+            spells.0 as i64,
+            spells.1 as i64,
+            serde_json::to_string(&rune_page)?,
+            patch,
+            unix_time()?
+        ],
+    )?;
+    Ok(())
+}
+
+/// Pin or unpin the stored pages for a champion, in one game mode or all of them. Returns the
+/// number of pages changed.
+fn set_pinned(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: Option<&str>,
+    pinned: bool,
+) -> Result<usize> {
+    Ok(conn.execute(
+        "update rune_pages set pinned = ?3 where champ_id = ?1 and (?2 is null or game_mode = ?2)",
+        params![champ_id as i64, game_mode, pinned],
+    )?)
+}
+
+fn pin_pages(conn: &Connection, champion: &str, game_mode: Option<&str>, pinned: bool) -> Result<()> {
+    let champ_id = champ_arg(champion)?;
+    let changed = set_pinned(conn, champ_id, game_mode, pinned)?;
+    if changed == 0 {
+        return Err(anyhow!("no stored page for {}", CHAMPIONS[&champ_id]));
+    }
+    let action = if pinned { "pinned" } else { "unpinned" };
+    println!("{} {} pages for {}", action, changed, CHAMPIONS[&champ_id]);
+    Ok(())
+}
+
+/// The client's game version, cut down to "major.minor", e.g. "14.23".
+fn get_patch(lcuclient: &LCUClient) -> Result<String> {
+    let version = lcuclient.get("/lol-patch/v1/game-version")?.text()?;
+    let version: String = serde_json::from_str(&version)?;
+    let patch: Vec<&str> = version.split('.').take(2).collect();
+    if patch.len() != 2 {
+        return Err(anyhow!("unexpected game version: {}", version));
+    }
+    Ok(patch.join("."))
+}
+
+fn report_stale_pages(conn: &Connection, patch: &str) -> Result<()> {
+    let (stale, untagged): (i64, i64) = conn.query_row(
+        "select count(*) filter (where patch != ?1), count(*) filter (where patch is null)
+            from rune_pages",
+        params![patch],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    log!("current patch: {}", patch);
+    if stale > 0 {
+        log!("stored pages from older patches: {}", stale);
+    }
+    if untagged > 0 {
+        log!("stored pages without a patch: {}", untagged);
+    }
+    Ok(())
+}
+
+fn get_stored_page(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+) -> Result<Option<((u64, u64), RunePage)>> {
+    let mut stmt = conn.prepare(
+        "select spell1_id, spell2_id, page from rune_pages where champ_id = ?1 and game_mode = ?2",
+    )?;
+    let mut rows = stmt.query(params![champ_id as i64, game_mode])?;
+    if let Some(row) = rows.next()? {
+        let spells: (i64, i64) = (row.get(0)?, row.get(1)?);
+        let page: String = row.get(2)?;
+        let page: RunePage = serde_json::from_str(&page)?;
+        return Ok(Some(((spells.0 as u64, spells.1 as u64), page)));
+    }
+    Ok(None)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct MobaBuild {
     win_rate: f64,
+    #[serde(default)]
+    pick_rate: Option<f64>,
+    /// How many games the rates are based on
+    #[serde(default, alias = "matches", alias = "matchCount")]
+    games: Option<u32>,
     perks: MobaPerks,
     spells: Vec<String>,
     name: String,
+    /* Kept as plain JSON, so a change in the item format doesn't break the rune pages */
+    #[serde(default)]
+    items: serde_json::Value,
+    /// Filled in from the role the build was listed under
+    #[serde(default)]
+    role: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct MobaPerks {
     ids: Vec<String>,
@@ -262,48 +629,198 @@ struct MobaPerks {
     sub_style: String,
 }
 
-fn row_to_data(row: &Row, champ_id: u64) -> Result<Vec<(RunePage, (u64, u64))>> {
+/// Why a candidate page wasn't set up in the client.
+#[derive(Debug, Clone, PartialEq)]
+enum Dropped {
+    /// Same runes and spells as a better page, named here
+    Duplicate(String),
+    /// No free page slots left
+    NoSpace,
+    /// Runes the client doesn't have (anymore)
+    Invalid(String),
+    /// Not enough games or picks behind a provider build
+    BelowThreshold(String),
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dropped::Duplicate(page) => write!(f, "duplicate of {}", page),
+            Dropped::NoSpace => write!(f, "no space"),
+            Dropped::Invalid(reason) => write!(f, "invalid, {}", reason),
+            Dropped::BelowThreshold(reason) => write!(f, "below threshold, {}", reason),
+        }
+    }
+}
+
+/// A page that could be set up in the client, and where it came from.
+#[derive(Debug, Clone)]
+struct Candidate {
+    page: RunePage,
+    spells: (u64, u64),
+    source: String,
+    /// The build provider's win rate, as a fraction like the other rates
+    win_rate: Option<f64>,
+    pick_rate: Option<f64>,
+    /// How many games the win rate is based on
+    games: Option<u32>,
+    /// The player's own win rate with the page
+    personal_win_rate: Option<f64>,
+    /// When the player last saved or played the page, in seconds since the epoch
+    last_used: Option<u64>,
+    pinned: bool,
+    /// The role the build is for, if the source knows
+    role: Option<&'static str>,
+    /// The enemy champion the build is meant for
+    vs: Option<u64>,
+    /// Saved on an older patch
+    stale: bool,
+    /// Filled in by `scoring::rank`
+    score: f64,
+    /// What each signal added to the score
+    score_parts: Vec<(&'static str, f64)>,
+    /// Set when the page isn't created
+    dropped: Option<Dropped>,
+}
+
+impl Candidate {
+    fn new(page: RunePage, spells: (u64, u64), source: &str) -> Self {
+        Candidate {
+            page,
+            spells,
+            source: source.to_string(),
+            win_rate: None,
+            pick_rate: None,
+            games: None,
+            personal_win_rate: None,
+            last_used: None,
+            pinned: false,
+            role: None,
+            vs: None,
+            stale: false,
+            score: 0.0,
+            score_parts: Vec::new(),
+            dropped: None,
+        }
+    }
+}
+
+type LocalInfo = (Vec<Candidate>, Vec<Candidate>);
+
+fn row_to_data(
+    row: &Row,
+    champ_id: u64,
+    patch: Option<&str>,
+    label: &str,
+    info: &mut LocalInfo,
+) -> Result<()> {
     let spells: (i64, i64) = (row.get(0)?, row.get(1)?);
     let spells: (u64, u64) = (spells.0 as u64, spells.1 as u64);
     let page: String = row.get(2)?;
+    let saved_patch: Option<String> = row.get(3)?;
+    let pinned: bool = row.get(4)?;
+    let saved_at: Option<i64> = row.get(5)?;
     let mut page: RunePage = serde_json::from_str(&page)?;
-    println!("found spells & page: {:?} {:?}", spells, page);
-    page.name = format!("{} (saved) {}", CHAMPIONS[&champ_id], MARKER);
-    Ok(vec![(page, spells)])
+    let candidate = |page| Candidate {
+        pinned,
+        last_used: saved_at.map(|saved_at| saved_at as u64),
+        ..Candidate::new(page, spells, "saved")
+    };
+    log!("found spells & page: {:?} {:?}", spells, page);
+    let label = if pinned { "pinned" } else { label };
+    match (saved_patch, patch) {
+        // Pinned pages were put there on purpose, so they stay on top regardless
+        (Some(saved_patch), Some(patch)) if saved_patch != patch => {
+            log!("page was saved on patch {}, current is {}", saved_patch, patch);
+            page.name = config()
+                .saved_page_name(CHAMPIONS[&champ_id], &format!("{} {}", label, saved_patch));
+            if pinned {
+                info.0.push(candidate(page));
+            } else {
+                info.1.push(candidate(page));
+            }
+        }
+        _ => {
+            page.name = config().saved_page_name(CHAMPIONS[&champ_id], label);
+            info.0.push(candidate(page));
+        }
+    }
+    Ok(())
 }
 
-fn get_local_info(
+fn find_local_row(
     conn: &Connection,
+    table: &str,
     champ_id: u64,
     game_mode: &str,
-) -> Result<Vec<(RunePage, (u64, u64))>> {
-    let mut stmt = conn.prepare(
-        "select spell1_id, spell2_id, page from rune_pages where champ_id = ?1 and game_mode = ?2",
-    )?;
+    patch: Option<&str>,
+    label: &str,
+    info: &mut LocalInfo,
+) -> Result<()> {
+    // The history table has no pinned column, those are never pinned
+    let pinned = if table == "rune_pages" { "pinned" } else { "0" };
+    let mut stmt = conn.prepare(&format!(
+        "select spell1_id, spell2_id, page, patch, {}, saved_at from {}
+            where champ_id = ?1 and game_mode = ?2",
+        pinned, table
+    ))?;
     let mut rows = stmt.query(params![champ_id as i64, game_mode])?;
     if let Some(row) = rows.next()? {
-        return row_to_data(row, champ_id);
+        return row_to_data(row, champ_id, patch, label, info);
     }
 
-    println!("couldn't find anything for champ and mode, trying just champ");
-    let mut stmt =
-        conn.prepare("select spell1_id, spell2_id, page from rune_pages where champ_id = ?1")?;
+    log!("couldn't find anything in {} for champ and mode, trying just champ", table);
+    let mut stmt = conn.prepare(&format!(
+        "select spell1_id, spell2_id, page, patch, {} as pinned, saved_at from {}
+            where champ_id = ?1 order by pinned desc",
+        pinned, table
+    ))?;
     let mut rows = stmt.query(params![champ_id as i64])?;
     if let Some(row) = rows.next()? {
-        return row_to_data(row, champ_id);
+        return row_to_data(row, champ_id, patch, label, info);
     }
-    // Just return an empty vec, so the next bit of code can add to it whether we found anything or not.
-    Ok(Vec::new())
+    Ok(())
 }
 
-#[cached(time = 64800, result = true)]
-fn get_mobalytics_info(champ_id: u64) -> Result<Vec<(RunePage, (u64, u64))>> {
-    let mut name = CHAMPIONS[&champ_id].to_string();
-    name.make_ascii_lowercase();
-    name = name
-        .chars()
-        .filter(|c| matches!(c, 'a'..='z'))
-        .collect();
+/// Returns pages saved on the current patch and pages saved on other patches separately, so
+/// the latter can be ranked below provider builds. Pinned pages always come first, followed by
+/// the last played page if the auto-save went to the history table.
+fn get_local_info(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+    patch: Option<&str>,
+) -> Result<LocalInfo> {
+    // Start with empty vecs, so the next bit of code can add to it whether we found anything or not.
+    let mut info = (Vec::new(), Vec::new());
+    find_local_row(conn, "rune_pages", champ_id, game_mode, patch, "saved", &mut info)?;
+    find_local_row(
+        conn,
+        "rune_page_history",
+        champ_id,
+        game_mode,
+        patch,
+        "last played",
+        &mut info,
+    )?;
+    Ok(info)
+}
+
+/// Mobalytics has given rates both as fractions and as percentages. Win rates are always around
+/// 50%, so they tell which one a response uses, and the rates are turned into fractions.
+fn moba_rates_to_fractions(builds: &mut [MobaBuild]) {
+    if !builds.iter().any(|build| build.win_rate > 1.0) {
+        return;
+    }
+    for build in builds {
+        build.win_rate /= 100.0;
+        build.pick_rate = build.pick_rate.map(|rate| rate / 100.0);
+    }
+}
+
+/// All of Mobalytics' builds for a champion, highest win rate first. Rates are fractions.
+fn get_mobalytics_builds(conn: &Connection, champ_id: u64) -> Result<Vec<MobaBuild>> {
+    let mut name = normalize_name(CHAMPIONS[&champ_id]);
 
     if name == "nunuwillump" {
         name.truncate(4);
@@ -313,18 +830,27 @@ fn get_mobalytics_info(champ_id: u64) -> Result<Vec<(RunePage, (u64, u64))>> {
         "https://api.mobalytics.gg/lol/champions/v1/meta?name={}",
         name
     );
-    println!("fetching {}", url);
-    let client = reqwest::blocking::Client::builder()
-        .timeout(Duration::from_secs(4))
-        .connect_timeout(Duration::from_secs(2))
-        .build()?;
-    let json = client.get(&url).send()?.text()?;
+    let json = cache::get(conn, &url, config().mobalytics_cache_secs)?;
     let mut json: serde_json::Value = serde_json::from_str(&json)?;
     let mut all_builds = Vec::<MobaBuild>::new();
     let roles = json["data"]["roles"].as_array_mut();
     if let Some(roles) = roles {
         for role in roles {
             let mut builds: Vec<MobaBuild> = serde_json::from_value(role["builds"].to_owned())?;
+            let name = role["name"].as_str().or(role["role"].as_str());
+            match name {
+                None => log!(
+                    "mobalytics role without a name, its builds won't match any role: {:?}",
+                    role.as_object().map(|role| role.keys().collect::<Vec<_>>())
+                ),
+                Some(name) if sources::normalize_role(name).is_none() => {
+                    log!("unknown mobalytics role {:?}, its builds won't match any role", name)
+                }
+                Some(_) => (),
+            }
+            for build in &mut builds {
+                build.role = name.map(str::to_string);
+            }
             all_builds.append(&mut builds);
         }
     }
@@ -334,12 +860,23 @@ fn get_mobalytics_info(champ_id: u64) -> Result<Vec<(RunePage, (u64, u64))>> {
             build.win_rate = 0.0;
         }
     }
+    moba_rates_to_fractions(&mut all_builds);
+    if all_builds.iter().any(|build| build.games.is_none()) {
+        static NO_GAMES: Once = Once::new();
+        NO_GAMES.call_once(|| {
+            log!("mobalytics builds have no game count, min_games won't apply to them")
+        });
+    }
     all_builds.sort_unstable_by(|a, b| b.win_rate.partial_cmp(&a.win_rate).unwrap());
-    all_builds
+    Ok(all_builds)
+}
+
+fn get_mobalytics_info(conn: &Connection, champ_id: u64) -> Result<Vec<Candidate>> {
+    get_mobalytics_builds(conn, champ_id)?
         .into_iter()
         .map(|build| {
             let page = RunePage {
-                name: format!("{} (mobalytics) {}", build.name, MARKER),
+                name: config().provider_page_name(&build.name, "mobalytics"),
                 primary_style_id: build.perks.style.parse()?,
                 sub_style_id: build.perks.sub_style.parse()?,
                 selected_perk_ids: build
@@ -352,7 +889,13 @@ fn get_mobalytics_info(champ_id: u64) -> Result<Vec<(RunePage, (u64, u64))>> {
             };
             let spell1_id: u64 = build.spells[0].parse()?;
             let spell2_id: u64 = build.spells[1].parse()?;
-            Ok((page, (spell1_id, spell2_id)))
+            Ok(Candidate {
+                win_rate: Some(build.win_rate),
+                pick_rate: build.pick_rate,
+                games: build.games,
+                role: build.role.as_deref().and_then(sources::normalize_role),
+                ..Candidate::new(page, (spell1_id, spell2_id), "mobalytics")
+            })
         })
         .collect()
 }
@@ -362,7 +905,9 @@ fn delete_page(lcuclient: &LCUClient, page: &RunePage) -> Result<()> {
     Ok(())
 }
 
-fn check_or_make_space(lcuclient: &LCUClient) -> Result<usize> {
+/// Delete our own pages and return how many pages can be created. With `dry_run`, only print
+/// what would be deleted, and return the space there would be.
+fn check_or_make_space(lcuclient: &LCUClient, dry_run: bool) -> Result<usize> {
     let pages = lcuclient.get("/lol-perks/v1/pages")?.text()?;
     let pages: Vec<RunePage> = serde_json::from_str(&pages)?;
 
@@ -370,7 +915,11 @@ fn check_or_make_space(lcuclient: &LCUClient) -> Result<usize> {
         .into_iter()
         .filter(|page| page.is_deletable)
         .filter_map(|page| {
-            if page.name.contains(MARKER) {
+            if page.name.contains(&config().marker) {
+                if dry_run {
+                    log!("would delete {} [id:{}]", page.name, page.id);
+                    return None;
+                }
                 match delete_page(lcuclient, &page) {
                     Ok(_) => None,
                     Err(e) => Some(Err(e)),
@@ -386,114 +935,412 @@ fn check_or_make_space(lcuclient: &LCUClient) -> Result<usize> {
         .unwrap() as usize;
     let available_space = max_pages - pages.len();
     if available_space == 0 {
-        println!("at max pages, deleting oldest");
+        if config().when_full == config::WhenFull::Skip {
+            log!("at max pages, not deleting any of yours");
+            return Ok(0);
+        }
+        log!("at max pages, deleting oldest");
         pages.sort_unstable_by(|a, b| a.last_modified.cmp(&b.last_modified));
-        delete_page(lcuclient, pages.first().context("No pages to delete?")?)?;
+        let oldest = pages.first().context("No pages to delete?")?;
+        if dry_run {
+            log!("would delete {} [id:{}]", oldest.name, oldest.id);
+        } else {
+            delete_page(lcuclient, oldest)?;
+        }
         return Ok(1);
     }
     Ok(available_space)
 }
 
-fn set_rune_page(lcuclient: &LCUClient, page: &RunePage) -> Result<()> {
+/// Create `page` in the client, returning the id it got.
+fn set_rune_page(lcuclient: &LCUClient, page: &RunePage) -> Result<u64> {
     let new_page = lcuclient.post("/lol-perks/v1/pages", "{}")?.text()?;
     let new_page: RunePage = serde_json::from_str(&new_page)?;
-    println!("created page, id: {}", new_page.id);
+    log!("created page, id: {}", new_page.id);
 
-    println!("making page {} with name: {}", new_page.id, page.name);
+    log!("making page {} with name: {}", new_page.id, page.name);
     let put = lcuclient.put(
         &format!("/lol-perks/v1/pages/{}", new_page.id),
         serde_json::to_string(&page)?,
     )?;
     if put.status() != 201 {
-        println!("{:?}", put.text()?);
+        log!("{:?}", put.text()?);
+        // Don't leave the empty page behind
+        delete_page(lcuclient, &new_page)?;
         return Err(anyhow!("rune page creation was not a 201"));
     }
-    println!("{:?}", put.text()?);
-    Ok(())
+    log!("{:?}", put.text()?);
+    Ok(new_page.id)
 }
 
-fn setup_runes_and_spells(
+/// Select the spells in champ select, in the order the placement rules want.
+fn set_spells(
     lcuclient: &LCUClient,
-    conn: &Connection,
     champ_id: u64,
-    game_mode: &str,
+    spells: (u64, u64),
+    dry_run: bool,
 ) -> Result<()> {
-    let mut available_space = check_or_make_space(lcuclient)?;
+    let spells = spells::place(champ_id, spells);
+    if dry_run {
+        log!("would set spells: {} {}", spells.0, spells.1);
+    } else {
+        let _ = lcuclient.patch(
+            "/lol-champ-select/v1/session/my-selection",
+            format!(
+                "{{ \"spell1Id\": {}, \"spell2Id\": {} }}",
+                spells.0, spells.1
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// The candidate at `index` with the champion and game mode it is for. The status lock is
+/// released before returning, since logging needs it too.
+fn candidate_at(index: usize) -> Result<(Candidate, u64, String)> {
+    let status = status::get();
+    let candidate = status
+        .candidates
+        .get(index)
+        .ok_or_else(|| anyhow!("no page {}", index + 1))?;
+    let champ_id = status.champ_id.ok_or_else(|| anyhow!("no champion yet"))?;
+    let game_mode = status
+        .game_mode
+        .clone()
+        .unwrap_or_else(|| "UNKNOWN".to_string());
+    Ok((candidate.clone(), champ_id, game_mode))
+}
 
-    let mut runes_and_spells = get_local_info(conn, champ_id, game_mode)?;
-    println!("after local, num pages: {}", runes_and_spells.len());
-    println!("looking up on mobalytics");
-    if let Ok(mut mobalytics) = get_mobalytics_info(champ_id) {
-        runes_and_spells.append(&mut mobalytics);
+/// Make one of the current candidates the current page, and select its spells.
+fn make_candidate_current(lcuclient: &LCUClient, index: usize, dry_run: bool) -> Result<()> {
+    let (candidate, champ_id, _) = candidate_at(index)?;
+    status::get().chosen = true;
+    if dry_run {
+        log!("would make {} current", candidate.page.name);
+        return Ok(());
     }
-    println!("after mobalytics, num pages: {}", runes_and_spells.len());
-    for (runes, _) in &mut runes_and_spells {
-        runes.selected_perk_ids.sort();
+    // Pages that didn't fit weren't created, make room and create them now
+    let id = match candidate.page.id {
+        0 => {
+            let space = check_or_make_space(lcuclient, dry_run)?;
+            // That deleted the other pages we made, they have to be created again as well
+            for stored in status::get().candidates.iter_mut() {
+                stored.page.id = 0;
+            }
+            if space == 0 {
+                return Err(anyhow!("no room for {}", candidate.page.name));
+            }
+            set_rune_page(lcuclient, &candidate.page)?
+        }
+        id => id,
+    };
+    lcuclient.put("/lol-perks/v1/currentpage", id.to_string())?;
+    set_spells(lcuclient, champ_id, candidate.spells, dry_run)?;
+    // The candidates might have been replaced in the meantime, so look it up again
+    for stored in status::get().candidates.iter_mut() {
+        if stored.page.name == candidate.page.name {
+            stored.page.id = id;
+        }
     }
+    log!("made {} current", candidate.page.name);
+    Ok(())
+}
 
-    // If runes and spells are the same, only keep one
-    let mut runes_and_spells: Vec<(RunePage, (u64, u64))> = runes_and_spells
-        .into_iter()
-        .fold(Vec::new(), |mut acc, (runes, spells)| {
-            if !acc.iter().any(|(previous_runes, previous_spells)| {
-                //println!("comparing \n{:?}\nwith\n{:?}\n", (&runes, spells), (previous_runes, previous_spells));
-                spells == *previous_spells
-                && runes.primary_style_id == previous_runes.primary_style_id
-                && runes.sub_style_id == previous_runes.sub_style_id
-                && runes.selected_perk_ids == previous_runes.selected_perk_ids
-            }) {
-                acc.push((runes, spells));
-            } else {
-                println!("found duplicate");
+/// Store one of the current candidates as the saved page for the champion.
+fn save_candidate(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    index: usize,
+    dry_run: bool,
+) -> Result<()> {
+    let (candidate, champ_id, game_mode) = candidate_at(index)?;
+    if dry_run {
+        log!(
+            "would save {} for {}",
+            candidate.page.name,
+            CHAMPIONS[&champ_id]
+        );
+        return Ok(());
+    }
+    let patch = get_patch(lcuclient).ok();
+    save_rune_page(
+        conn,
+        champ_id,
+        candidate.spells,
+        &game_mode,
+        &candidate.page,
+        patch.as_deref(),
+    )?;
+    log!("saved {} for {}", candidate.page.name, CHAMPIONS[&champ_id]);
+    Ok(())
+}
+
+/// Fetch pages from every source and decide which ones to create, best first. Pages that
+/// won't be created are kept with the reason in `dropped`. Without a client the pages aren't
+/// validated, and without `space` none are dropped for lack of it.
+fn choose_candidates(
+    lcuclient: Option<&LCUClient>,
+    context: &sources::Context,
+    space: Option<usize>,
+) -> Vec<Candidate> {
+    let mut candidates = sources::registry().fetch(context);
+
+    match lcuclient.map(perks::get_perk_data) {
+        Some(Ok(perk_data)) => {
+            for candidate in candidates.iter_mut().filter(|c| c.dropped.is_none()) {
+                let runes = &mut candidate.page;
+                match perk_data.check(runes) {
+                    perks::Check::Valid => (),
+                    perks::Check::Repaired(changes) => {
+                        log!("repaired {}: {}", runes.name, changes.join(", "));
+                    }
+                    perks::Check::Broken(reason) => {
+                        log!("skipping {}: {}", runes.name, reason);
+                        candidate.dropped = Some(Dropped::Invalid(reason));
+                    }
+                }
             }
-            acc
+        }
+        Some(Err(e)) => log!("couldn't get perk data, not validating pages: {}", e),
+        None => (),
+    }
+
+    // If runes and spells are the same, only keep the best one
+    for index in 0..candidates.len() {
+        if candidates[index].dropped.is_some() {
+            continue;
+        }
+        let (runes, spells) = (&candidates[index].page, candidates[index].spells);
+        let previous = candidates[..index].iter().find(|previous| {
+            previous.dropped.is_none()
+                && spells == previous.spells
+                && runes.same_runes(&previous.page)
         });
-    println!("after deduplication, num pages: {}", runes_and_spells.len());
+        if let Some(previous) = previous {
+            log!("found duplicate");
+            candidates[index].dropped = Some(Dropped::Duplicate(previous.page.name.clone()));
+        }
+    }
+    let kept = candidates.iter().filter(|c| c.dropped.is_none()).count();
+    log!("after validation and deduplication, num pages: {}", kept);
 
-    let len = runes_and_spells.len();
-    if len > available_space {
-        println!(
-            "Have {} pages, but there's only room for {}",
-            len, available_space
-        );
-        runes_and_spells.drain(available_space..);
+    if let Some(space) = space {
+        if kept > space {
+            log!("Have {} pages, but there's only room for {}", kept, space);
+        }
+        for candidate in candidates
+            .iter_mut()
+            .filter(|c| c.dropped.is_none())
+            .skip(space)
+        {
+            candidate.dropped = Some(Dropped::NoSpace);
+        }
     }
-    println!("after size adjustment, num pages: {}", runes_and_spells.len());
+    candidates
+}
 
-    println!("num pages: {}", runes_and_spells.len());
+/// Set up the best pages and their spells in the client. Returns every candidate, including
+/// the ones that weren't created. With `dry_run` nothing is changed in the client, the pages and
+/// spells that would be set are printed instead.
+fn setup_runes_and_spells(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+    position: Option<&str>,
+    opponents: &sources::Opponents,
+    dry_run: bool,
+) -> Result<Vec<Candidate>> {
+    let available_space = check_or_make_space(lcuclient, dry_run)?;
 
-    // Reverse so highest winrate is installed last (and thus is active at the end)
-    runes_and_spells.reverse();
+    let patch = get_patch(lcuclient).ok();
+    let context = sources::Context {
+        lcuclient: Some(lcuclient),
+        conn,
+        champ_id,
+        game_mode,
+        position,
+        patch: patch.as_deref(),
+        opponents,
+    };
+    let mut candidates = choose_candidates(Some(lcuclient), &context, Some(available_space));
+    let created: Vec<usize> = (0..candidates.len())
+        .filter(|index| candidates[*index].dropped.is_none())
+        .collect();
+    log!("num pages: {}", created.len());
 
-    for (index, (page, mut spells)) in runes_and_spells.into_iter().enumerate() {
-        if available_space == 0 {
-            break;
-        }
-        set_rune_page(lcuclient, &page)?;
-        if index == 0 {
-            if spells.0 == 4 {
-                spells.0 = spells.1;
-                spells.1 = 4;
+    // Go in reverse so the best page is created last, which makes it the active one
+    let mut current = None;
+    for &index in created.iter().rev() {
+        let page = &mut candidates[index].page;
+        if dry_run {
+            log!(
+                "would create {}: styles {}/{} perks {:?}",
+                page.name,
+                page.primary_style_id,
+                page.sub_style_id,
+                page.selected_perk_ids
+            );
+        } else {
+            match set_rune_page(lcuclient, page) {
+                Ok(id) => page.id = id,
+                Err(e) => {
+                    // One bad page shouldn't stop the rest from being set up
+                    log!("couldn't create {}: {}", page.name, e);
+                    continue;
+                }
             }
-            let _ = lcuclient.patch(
-                "/lol-champ-select/v1/session/my-selection",
-                format!(
-                    "{{ \"spell1Id\": {}, \"spell2Id\": {} }}",
-                    spells.0, spells.1
-                ),
-            )?;
         }
-        available_space -= 1;
+        current = Some(index);
     }
-    Ok(())
+    // Spells go with the page that ended up active
+    if let Some(index) = current {
+        set_spells(lcuclient, champ_id, candidates[index].spells, dry_run)?;
+    }
+    // Pages that don't fit are still kept as candidates, they just aren't created
+    let (usable, dropped) = candidates
+        .iter()
+        .cloned()
+        .partition(|c| matches!(c.dropped, None | Some(Dropped::NoSpace)));
+    {
+        let mut status = status::get();
+        status.candidates = usable;
+        status.dropped = dropped;
+    }
+    Ok(candidates)
 }
 
 fn main() -> Result<()> {
     //    let page = get_mobalytics_page(875);
     //    todo!();
 
+    let cli = Cli::parse();
+    config::load(cli.config.as_deref())?;
+    lcu::set_discovery_interval(config().discovery_interval_secs);
     let conn = setup_sqlite()?;
 
+    match cli.command.unwrap_or(Command::Run { tui: false }) {
+        Command::Export { .. }
+        | Command::Pin { .. }
+        | Command::Unpin { .. }
+        | Command::Cache { .. }
+            if cli.dry_run =>
+        {
+            Err(anyhow!("--dry-run isn't supported by this command"))
+        }
+        Command::Run { tui } => {
+            let (jobs_sender, jobs) = channel();
+            if tui {
+                #[cfg(feature = "tui")]
+                tui::start(jobs_sender.clone())?;
+                #[cfg(not(feature = "tui"))]
+                return Err(anyhow!("built without the tui feature"));
+            }
+            if let Some(port) = config().api_port {
+                let token_path = config::api_token_path(cli.config.as_deref())
+                    .context("no config directory for the API token")?;
+                api::start(port, &token_path, jobs_sender)?;
+            }
+            run(&conn, &jobs, cli.dry_run)
+        }
+        Command::List { champion } => {
+            let champ_id = champion.as_deref().map(champ_arg).transpose()?;
+            commands::list(&conn, champ_id)
+        }
+        Command::Show { champion, mode } => {
+            commands::show(&conn, champ_arg(&champion)?, mode.as_deref())
+        }
+        Command::Delete { champion, mode } => {
+            commands::delete(&conn, champ_arg(&champion)?, mode.as_deref(), cli.dry_run)
+        }
+        Command::Apply {
+            champion,
+            mode,
+            role,
+            vs,
+            explain,
+        } => {
+            let lcuclient = LCUClient::new()?;
+            let champ_id = champ_arg(&champion)?;
+            let opponents = opponents_arg(vs.as_deref())?;
+            let candidates = setup_runes_and_spells(
+                &lcuclient,
+                &conn,
+                champ_id,
+                &mode,
+                role,
+                &opponents,
+                cli.dry_run,
+            )?;
+            if explain {
+                commands::print_explanation(&candidates);
+            }
+            Ok(())
+        }
+        Command::Sources {
+            champion,
+            mode,
+            role,
+            vs,
+            explain,
+        } => {
+            let opponents = opponents_arg(vs.as_deref())?;
+            let champ_id = champ_arg(&champion)?;
+            commands::sources(&conn, champ_id, &mode, role, &opponents, explain)
+        }
+        Command::Export { file, format } => transfer::export(&conn, &file, format),
+        Command::Import {
+            file,
+            format,
+            on_conflict,
+        } => transfer::import(&conn, &file, format, on_conflict, cli.dry_run),
+        Command::Encode { champion, mode } => {
+            let champ_id = champ_arg(&champion)?;
+            let shared = share::stored_page(&conn, champ_id, mode.as_deref())?
+                .ok_or_else(|| anyhow!("no stored page for {}", CHAMPIONS[&champ_id]))?;
+            println!("{}", share::encode(&shared)?);
+            Ok(())
+        }
+        Command::Decode { code, mode, client } => {
+            let shared = share::decode(&code)?;
+            println!(
+                "page: {} {:?}, spells: {:?}",
+                shared.page.name, shared.page.selected_perk_ids, shared.spells
+            );
+            if client {
+                let lcuclient = LCUClient::new()?;
+                return share::push_to_client(&lcuclient, &shared, cli.dry_run);
+            }
+            share::store(&conn, &shared, &mode, cli.dry_run)
+        }
+        Command::Snapshot { name } => {
+            let lcuclient = LCUClient::new()?;
+            snapshot::take(&lcuclient, &conn, name.as_deref(), cli.dry_run)
+        }
+        Command::Snapshots => snapshot::list(&conn),
+        Command::Cache { command } => cache::run(&conn, command),
+        Command::Pin { champion, mode } => pin_pages(&conn, &champion, mode.as_deref(), true),
+        Command::Unpin { champion, mode } => pin_pages(&conn, &champion, mode.as_deref(), false),
+        Command::VerifyDb { repair } => {
+            let lcuclient = LCUClient::new()?;
+            perks::verify_db(&lcuclient, &conn, repair, cli.dry_run)
+        }
+        Command::Restore { snapshot } => {
+            let lcuclient = LCUClient::new()?;
+            snapshot::restore(&lcuclient, &conn, &snapshot, cli.dry_run)
+        }
+        Command::Backfill { games } => {
+            let lcuclient = LCUClient::new()?;
+            match_history::backfill(&lcuclient, &conn, games, cli.dry_run)
+        }
+    }
+}
+
+/// Work for the event loop from the API or the dashboard, run there so it can't set up pages
+/// at the same time as the event loop does. Gets the client, the database and `dry_run`.
+type Job = Box<dyn FnOnce(&LCUClient, &Connection, bool) + Send>;
+
+fn run(conn: &Connection, jobs: &Receiver<Job>, dry_run: bool) -> Result<()> {
     let mut stmt =
         conn.prepare("select champ_id, game_mode, spell1_id, spell2_id, page from rune_pages")?;
     let mut rows = stmt.query([])?;
@@ -503,28 +1350,42 @@ fn main() -> Result<()> {
         num += 1;
     }
 
-    println!("stored pages: {}", num);
+    log!("stored pages: {}", num);
     loop {
-        let _ = run_event_loop(&conn);
+        let _ = run_event_loop(conn, jobs, dry_run);
     }
 }
 
-fn run_event_loop(conn: &Connection) -> Result<()> {
+fn run_event_loop(
+    conn: &Connection,
+    jobs: &Receiver<Job>,
+    dry_run: bool,
+) -> Result<()> {
     let mut game_mode: Option<String> = None;
     let mut rune_page: Option<RunePage> = None;
     let mut champ_id: Option<u64> = None;
     let mut phase: Option<GamePhase> = None;
     let mut spells: Option<(u64, u64)> = None;
+    let mut position: Option<&'static str> = None;
+    let mut opponents = sources::Opponents::default();
+    // The champion changed while paused, so set up pages once resumed
+    let mut pending_setup = false;
 
     let lcuclient = LCUClient::new()?;
-    if clean_pages(&lcuclient).is_err() {
-        println!("LCU not returning data, sleeping...");
+    if clean_pages(&lcuclient, dry_run).is_err() {
+        log!("LCU not returning data, sleeping...");
         thread::sleep(time::Duration::from_secs(2));
         return Ok(());
     }
+    if let Ok(patch) = get_patch(&lcuclient) {
+        report_stale_pages(conn, &patch)?;
+    }
 
     let (champ_sender, champ_receiver) = channel();
     let (spells_sender, spells_receiver) = channel();
+    let (opponents_sender, opponents_receiver) = channel();
+    let (likely_sender, likely_receiver) = channel();
+    let mut unknown_position = String::new();
     let mut ws = LCUWebSocket::new();
     ws.subscribe(
         "OnJsonApiEvent_lol-champ-select_v1_session".to_string(),
@@ -535,10 +1396,48 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
             let session: Result<SelectSession, _> = serde_json::from_str(&session);
             if let Ok(session) = session {
                 let local_player_cell_id = session.local_player_cell_id;
+                // Champions we might end up playing: our own pick first, then the ones we could
+                // trade for with teammates or take from the bench
+                let likely: Vec<u64> = session
+                    .my_team
+                    .iter()
+                    .filter(|player| player.cell_id == local_player_cell_id)
+                    .chain(
+                        session
+                            .my_team
+                            .iter()
+                            .filter(|player| player.cell_id != local_player_cell_id),
+                    )
+                    .flat_map(|player| [player.champion_id, player.champion_pick_intent])
+                    .chain(
+                        session
+                            .bench_champions
+                            .iter()
+                            .map(|bench| bench.champion_id),
+                    )
+                    .filter(|champ_id| *champ_id != 0)
+                    .collect();
+                likely_sender.send(likely)?;
                 let me = session
                     .my_team
                     .into_iter()
                     .find(|player| player.cell_id == local_player_cell_id);
+                // Enemy picks stay hidden in blind pick, those are just 0
+                let enemies: Vec<&SessionPlayer> = session
+                    .their_team
+                    .iter()
+                    .filter(|player| player.champion_id != 0)
+                    .collect();
+                let laner = me.as_ref().and_then(|me| {
+                    enemies.iter().find(|player| {
+                        !me.assigned_position.is_empty()
+                            && player.assigned_position == me.assigned_position
+                    })
+                });
+                opponents_sender.send(sources::Opponents {
+                    enemies: enemies.iter().map(|player| player.champion_id).collect(),
+                    laner: laner.map(|player| player.champion_id),
+                })?;
                 if let Some(me) = me {
                     let champ_id = if me.champion_id != 0 {
                         me.champion_id
@@ -546,7 +1445,16 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
                         me.champion_pick_intent
                     };
                     if champ_id != 0 {
-                        champ_sender.send(champ_id)?;
+                        let position = sources::normalize_role(&me.assigned_position);
+                        // Empty in blind pick, anything else should be a known role
+                        if position.is_none()
+                            && !me.assigned_position.is_empty()
+                            && me.assigned_position != unknown_position
+                        {
+                            log!("unknown position {:?}", me.assigned_position);
+                            unknown_position = me.assigned_position.clone();
+                        }
+                        champ_sender.send((champ_id, position))?;
                     }
                     if me.spell1_id != 0 && me.spell2_id != 0 {
                         spells_sender.send((me.spell1_id, me.spell2_id))?
@@ -562,7 +1470,7 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
     ws.subscribe(
         "OnJsonApiEvent_lol-gameflow_v1_session".to_string(),
         move |json| {
-            //println!("{}", serde_json::to_string_pretty(&json)?);
+            //log!("{}", serde_json::to_string_pretty(&json)?);
             let gm = json["data"]["gameData"]["queue"]["gameMode"]
                 .as_str()
                 .context("no game mode")?
@@ -589,6 +1497,39 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
         },
     );
 
+    let (chat_sender, chat_receiver) = channel();
+    if config().chat_commands {
+        if config().chat_replies {
+            log!("warning: chat_replies is on, your team may see the page list in chat");
+        }
+        match chat::my_id(&lcuclient) {
+            Ok(me) => {
+                ws.subscribe(
+                    "OnJsonApiEvent_lol-chat_v1_conversations".to_string(),
+                    move |json| {
+                        let is_message = json["uri"]
+                            .as_str()
+                            .is_some_and(|uri| uri.contains("/messages/"));
+                        let data = &json["data"];
+                        // Only new messages from us count, and not the ones we post ourselves
+                        if json["eventType"] != "Create"
+                            || !is_message
+                            || data["fromId"].as_str() != Some(me.as_str())
+                            || data["type"] == "celebration"
+                        {
+                            return Ok(());
+                        }
+                        if let Some(command) = data["body"].as_str().and_then(chat::parse) {
+                            chat_sender.send(command)?;
+                        }
+                        Ok(())
+                    },
+                );
+            }
+            Err(e) => log!("couldn't get chat id, no chat commands: {}", e),
+        }
+    }
+
     let (runes_sender, runes_receiver) = channel();
     ws.subscribe(
         "OnJsonApiEvent_lol-perks_v1_currentpage".to_string(),
@@ -610,20 +1551,27 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
         let all_file_sender = sender.clone();
         ws.subscribe("OnJsonApiEvent".to_string(), move |json| {
             all_file_sender.send(format!("{}", serde_json::to_string_pretty(&json)?))?;
-            println!("{}", json["uri"].as_str()?);
+            log!("{}", json["uri"].as_str()?);
             LCUWSResult::Continue
         });
     */
 
+    let mut prefetcher = prefetch::start().unwrap_or_else(|e| {
+        log!("couldn't start prefetching: {}", e);
+        None
+    });
+
     while let Ok(()) = ws.dispatch() {
         while let Ok(new_gm) = gm_receiver.try_recv() {
             match game_mode {
                 None => {
-                    println!("Game mode: {}", new_gm);
+                    log!("Game mode: {}", new_gm);
+                    status::get().game_mode = Some(new_gm.clone());
                     game_mode = Some(new_gm);
                 }
                 Some(prevgm) if prevgm != new_gm => {
-                    println!("Game mode: {}", new_gm);
+                    log!("Game mode: {}", new_gm);
+                    status::get().game_mode = Some(new_gm.clone());
                     game_mode = Some(new_gm);
                 }
                 Some(_) => (),
@@ -633,27 +1581,148 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
         while let Ok(runes) = runes_receiver.try_recv() {
             let prev_rune_name = &rune_page.as_ref().map(|r| r.name.clone());
             rune_page = runes;
+            status::get().rune_page = rune_page.clone();
             if prev_rune_name != &rune_page.as_ref().map(|r| r.name.clone()) {
+                status::publish(
+                    "page",
+                    serde_json::json!({
+                        "id": rune_page.as_ref().map(|r| r.id),
+                        "name": rune_page.as_ref().map(|r| &r.name),
+                    }),
+                );
                 if let Some(runes) = &rune_page {
-                    println!("Rune page: {:?}", runes.name);
+                    log!("Rune page: {:?}", runes.name);
                 } else {
-                    println!("No rune page");
+                    log!("No rune page");
                 }
             }
         }
 
-        while let Ok(cid) = champ_receiver.try_recv() {
+        while let Ok(likely) = likely_receiver.try_recv() {
+            if let Some(prefetcher) = &mut prefetcher {
+                prefetcher.request(likely);
+            }
+        }
+
+        // Pages get picked again when the lane opponent changes, or an enemy is picked that there
+        // are builds against, unless the champion changes too
+        let mut reevaluate = false;
+        let mut new_enemies = Vec::new();
+        while let Ok(new_opponents) = opponents_receiver.try_recv() {
+            if new_opponents != opponents {
+                reevaluate |= new_opponents.laner != opponents.laner;
+                new_enemies.extend(
+                    new_opponents
+                        .enemies
+                        .iter()
+                        .filter(|enemy| !opponents.enemies.contains(enemy)),
+                );
+                opponents = new_opponents;
+                log!(
+                    "Enemies: {:?}, lane opponent: {:?}",
+                    opponents.enemies,
+                    opponents.laner
+                );
+                status::get().opponents = opponents.clone();
+                status::publish(
+                    "enemies",
+                    serde_json::json!({
+                        "enemies": opponents.enemies,
+                        "laner": opponents.laner,
+                    }),
+                );
+            }
+        }
+
+        while let Ok((cid, new_position)) = champ_receiver.try_recv() {
             let prev_champ_id = champ_id;
             champ_id = Some(cid);
+            position = new_position;
             if prev_champ_id != champ_id {
-                println!("Champ ID: {:?}", cid);
-                if let Some(game_mode) = &game_mode {
-                    println!("setup runes");
-                    setup_runes_and_spells(&lcuclient, conn, cid, game_mode)?;
-                } else {
-                    println!("got champ_id, but no qid, using UNKNOWN");
-                    setup_runes_and_spells(&lcuclient, conn, cid, "UNKNOWN")?;
+                log!("Champ ID: {:?}, position: {:?}", cid, position);
+                status::get().champ_id = champ_id;
+                status::get().position = position;
+                status::publish(
+                    "champion",
+                    serde_json::json!({ "champId": cid, "champion": CHAMPIONS.get(&cid) }),
+                );
+                if status::get().paused {
+                    log!("paused, setting up pages once resumed");
                 }
+                status::get().chosen = false;
+                pending_setup = true;
+            }
+        }
+
+        let paused = status::get().paused;
+        if let (true, false, Some(cid)) = (pending_setup, paused, champ_id) {
+            pending_setup = false;
+            reevaluate = false;
+            if game_mode.is_none() {
+                log!("got champ_id, but no qid, using UNKNOWN");
+            }
+            let mode = game_mode.as_deref().unwrap_or("UNKNOWN");
+            log!("setup runes");
+            setup_runes_and_spells(&lcuclient, conn, cid, mode, position, &opponents, dry_run)?;
+            if dry_run {
+                log!("not setting up item sets in a dry run");
+            } else if let Err(e) = itemsets::apply(&lcuclient, conn, cid, mode) {
+                log!("couldn't set up item sets: {}", e);
+            }
+            if config().chat_commands {
+                if let Err(e) = chat::post_candidates(&lcuclient, dry_run) {
+                    log!("couldn't post pages in chat: {}", e);
+                }
+            }
+        }
+
+        if let (false, false, Some(cid)) = (reevaluate, pending_setup, champ_id) {
+            if !new_enemies.is_empty() {
+                let patch = get_patch(&lcuclient).ok();
+                let context = sources::Context {
+                    lcuclient: Some(&lcuclient),
+                    conn,
+                    champ_id: cid,
+                    game_mode: game_mode.as_deref().unwrap_or("UNKNOWN"),
+                    position,
+                    patch: patch.as_deref(),
+                    opponents: &opponents,
+                };
+                reevaluate = sources::has_matchup_pages(&context, &new_enemies);
+            }
+        }
+        if let (true, false, Some(cid)) = (reevaluate, pending_setup, champ_id) {
+            let (paused, chosen) = {
+                let status = status::get();
+                (status.paused, status.chosen)
+            };
+            if paused {
+                log!("paused, not updating pages for the enemy team");
+            } else if chosen {
+                log!("enemy team changed, keeping the page that was picked");
+            } else {
+                log!("enemy team changed, setting up runes again");
+                let mode = game_mode.as_deref().unwrap_or("UNKNOWN");
+                if let Err(e) = setup_runes_and_spells(
+                    &lcuclient, conn, cid, mode, position, &opponents, dry_run,
+                ) {
+                    log!("couldn't set up runes again: {}", e);
+                } else if config().chat_commands {
+                    if let Err(e) = chat::post_candidates(&lcuclient, dry_run) {
+                        log!("couldn't post pages in chat: {}", e);
+                    }
+                }
+            }
+        }
+
+        while let Ok(job) = jobs.try_recv() {
+            job(&lcuclient, conn, dry_run);
+        }
+
+        while let Ok(command) = chat_receiver.try_recv() {
+            log!("chat command: {:?}", command);
+            if let Err(e) = chat::handle(&lcuclient, conn, command, dry_run) {
+                log!("couldn't do chat command: {}", e);
             }
         }
 
@@ -661,7 +1730,8 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
             let prev_spells = spells;
             spells = Some(sp);
             if prev_spells != spells {
-                println!("Spells: {} {}", sp.0, sp.1);
+                status::get().spells = spells;
+                log!("Spells: {} {}", sp.0, sp.1);
             }
         }
 
@@ -669,46 +1739,110 @@ fn run_event_loop(conn: &Connection) -> Result<()> {
             let prev_phase = phase;
             phase = p;
             if prev_phase != phase {
+                status::get().phase = phase;
+                status::publish(
+                    "phase",
+                    serde_json::json!({ "phase": phase.map(|p| format!("{:?}", p)) }),
+                );
+                if phase != Some(GamePhase::ChampSelect) {
+                    pending_setup = false;
+                    status::get().chosen = false;
+                }
                 if let Some(p) = phase {
-                    if p == GamePhase::GameStart {
+                    if let (GamePhase::Lobby | GamePhase::Matchmaking, Some(prefetcher)) =
+                        (p, &mut prefetcher)
+                    {
+                        if p == GamePhase::Lobby {
+                            prefetcher.reset();
+                        }
+                        match prefetch::pool(conn) {
+                            Ok(pool) => prefetcher.request_pool(pool),
+                            Err(e) => log!("couldn't read the champion pool: {}", e),
+                        }
+                    }
+                    if p == GamePhase::GameStart && status::get().paused {
+                        log!("paused, not saving the rune page");
+                    } else if p == GamePhase::GameStart {
                         let rune_page = lcuclient.get("/lol-perks/v1/currentpage")?.text()?;
                         let rune_page: RunePage = serde_json::from_str(&rune_page)?;
                         if let (Some(champ_id), Some(spells), Some(game_mode)) =
                             (champ_id, spells, &game_mode)
                         {
-                            println!("Saving rune page");
-                            save_rune_page(conn, champ_id, spells, game_mode, &rune_page)?;
+                            if dry_run {
+                                let enemies: Vec<&str> = opponents
+                                    .enemies
+                                    .iter()
+                                    .map(|enemy| CHAMPIONS.get(enemy).copied().unwrap_or("?"))
+                                    .collect();
+                                log!(
+                                    "would save {} and its item sets, against {:?}",
+                                    rune_page.name,
+                                    enemies
+                                );
+                            } else {
+                                log!("Saving rune page");
+                                let patch = get_patch(&lcuclient).ok();
+                                autosave_rune_page(
+                                    conn,
+                                    champ_id,
+                                    spells,
+                                    game_mode,
+                                    &rune_page,
+                                    patch.as_deref(),
+                                )?;
+                                // Enemy roles are usually hidden, so save it against all of them
+                                for &enemy in &opponents.enemies {
+                                    log!(
+                                        "Saving rune page against {}",
+                                        CHAMPIONS.get(&enemy).copied().unwrap_or("?")
+                                    );
+                                    matchups::save(
+                                        conn,
+                                        champ_id,
+                                        game_mode,
+                                        enemy,
+                                        spells,
+                                        &rune_page,
+                                        patch.as_deref(),
+                                    )?;
+                                }
+                                if let Err(e) =
+                                    itemsets::save(&lcuclient, conn, champ_id, game_mode)
+                                {
+                                    log!("couldn't save item sets: {}", e);
+                                }
+                            }
                         } else {
                             if champ_id == None {
-                                println!("Missing champ_id");
+                                log!("Missing champ_id");
                             }
                             if spells == None {
-                                println!("Missing spells");
+                                log!("Missing spells");
                             }
                             if game_mode == None {
-                                println!("Missing game mode");
+                                log!("Missing game mode");
                             }
-                            println!("Missing champ_id/spells/game mode/runes at game start");
+                            log!("Missing champ_id/spells/game mode/runes at game start");
                         }
                     }
-                    println!("Phase: {:?}", p);
+                    log!("Phase: {:?}", p);
                 } else {
-                    println!("No phase");
+                    log!("No phase");
                 }
             }
         }
-        //println!("while loop");
+        //log!("while loop");
     }
     Ok(())
 }
 
-fn clean_pages(lcuclient: &LCUClient) -> Result<()> {
+fn clean_pages(lcuclient: &LCUClient, dry_run: bool) -> Result<()> {
     let pages = lcuclient.get("/lol-perks/v1/pages")?.text()?;
     let pages: Vec<RunePage> = serde_json::from_str(&pages)?;
     if pages.is_empty() {
         return Err(anyhow!("No pages yet"));
     } else {
-        println!("{} rune pages in client", pages.len());
+        log!("{} rune pages in client", pages.len());
     }
     let mut pages: Vec<RunePage> = pages.into_iter().filter(|page| page.is_deletable).collect();
 
@@ -721,11 +1855,13 @@ fn clean_pages(lcuclient: &LCUClient) -> Result<()> {
         }
     });
 
-    println!("all deletable pages:");
+    log!("all deletable pages:");
     for page in pages.iter() {
-        println!(
+        log!(
             "  {} [id:{}] [lm:{}]",
-            page.name, page.id, page.last_modified
+            page.name,
+            page.id,
+            page.last_modified
         );
     }
 
@@ -733,24 +1869,64 @@ fn clean_pages(lcuclient: &LCUClient) -> Result<()> {
     let mut pages_to_delete = Vec::new();
     while let Some(page) = peekable.next() {
         if let Some(next) = peekable.peek() {
-            if next.name == page.name || !page.is_valid {
+            // Only our own pages, the player's duplicates are none of our business
+            if (next.name == page.name || !page.is_valid) && page.name.contains(&config().marker) {
                 pages_to_delete.push(page);
             }
         }
     }
 
     // XXX: make sure there's an empty slot 
-    if pages_to_delete.is_empty() {
-        println!("deleting:");
+    if !pages_to_delete.is_empty() {
+        if dry_run {
+            log!("would delete:");
+        } else {
+            log!("deleting:");
+        }
         for page in pages_to_delete.into_iter() {
-            println!(
+            log!(
                 "  {} [id:{}] [lm:{}]",
-                page.name, page.id, page.last_modified
+                page.name,
+                page.id,
+                page.last_modified
             );
-            delete_page(lcuclient, &page)?;
+            if !dry_run {
+                delete_page(lcuclient, &page)?;
+            }
         }
     } else {
-        println!("nothing to clean");
+        log!("nothing to clean");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moba_rates_become_fractions() {
+        let build = |win_rate: f64, pick_rate: f64| -> MobaBuild {
+            serde_json::from_value(serde_json::json!({
+                "winRate": win_rate,
+                "pickRate": pick_rate,
+                "perks": { "ids": [], "style": "8100", "subStyle": "8200" },
+                "spells": ["4", "14"],
+                "name": "build",
+            }))
+            .unwrap()
+        };
+        let mut percent = vec![build(52.5, 8.0), build(48.0, 0.5)];
+        moba_rates_to_fractions(&mut percent);
+        assert_eq!(percent[0].win_rate, 0.525);
+        assert_eq!(percent[0].pick_rate, Some(0.08));
+        // A pick rate under 1% is still read as a percentage
+        assert_eq!(percent[1].pick_rate, Some(0.005));
+        assert_eq!(percent[1].games, None);
+
+        let mut fractions = vec![build(0.525, 0.08)];
+        moba_rates_to_fractions(&mut fractions);
+        assert_eq!(fractions[0].win_rate, 0.525);
+        assert_eq!(fractions[0].pick_rate, Some(0.08));
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{anyhow, Context as _, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::config::config;
+use crate::lcu::LCUClient;
+use crate::perks::{self, Check};
+use crate::sources::{BuildSource, Context};
+use crate::{save_rune_page, Candidate, RunePage, CHAMPIONS};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MatchHistoryResult {
+    games: MatchHistoryGames,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MatchHistoryGames {
+    games: Vec<MatchHistoryMatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MatchHistoryMatch {
+    game_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Participant {
+    participant_id: u64,
+    champion_id: u64,
+    spell1_id: u64,
+    spell2_id: u64,
+    stats: ParticipantStats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ParticipantStats {
+    win: bool,
+    perk_primary_style: i64,
+    perk_sub_style: i64,
+    perk0: i64,
+    perk1: i64,
+    perk2: i64,
+    perk3: i64,
+    perk4: i64,
+    perk5: i64,
+    stat_perk0: i64,
+    stat_perk1: i64,
+    stat_perk2: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Player {
+    #[serde(default)]
+    puuid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ParticipantIdentity {
+    participant_id: u64,
+    player: Player,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MatchDetails {
+    game_id: u64,
+    /// Milliseconds since the epoch
+    game_creation: u64,
+    game_mode: String,
+    game_version: String,
+    participants: Vec<Participant>,
+    participant_identities: Vec<ParticipantIdentity>,
+}
+
+/// What the player took into one game.
+#[derive(Debug, Clone)]
+pub struct PlayedGame {
+    pub champ_id: u64,
+    pub game_mode: String,
+    /// When the game started, in milliseconds since the epoch
+    pub created: u64,
+    /// Major and minor version, like `get_patch`
+    pub patch: String,
+    pub page: RunePage,
+    pub spells: (u64, u64),
+    pub win: bool,
+}
+
+pub fn setup_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists match_details (
+                game_id integer primary key on conflict replace,
+                details text not null
+            )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn my_puuid(lcuclient: &LCUClient) -> Result<String> {
+    let summoner = lcuclient.get("/lol-summoner/v1/current-summoner")?.text()?;
+    let summoner: Value = serde_json::from_str(&summoner)?;
+    Ok(summoner["puuid"]
+        .as_str()
+        .context("no puuid for current summoner")?
+        .to_string())
+}
+
+fn played_game(details: MatchDetails, puuid: &str) -> Result<PlayedGame> {
+    let participant_id = details
+        .participant_identities
+        .iter()
+        .find(|identity| identity.player.puuid == puuid)
+        .ok_or_else(|| anyhow!("not in game {}", details.game_id))?
+        .participant_id;
+    let me = details
+        .participants
+        .into_iter()
+        .find(|participant| participant.participant_id == participant_id)
+        .ok_or_else(|| anyhow!("no stats in game {}", details.game_id))?;
+    let stats = me.stats;
+    Ok(PlayedGame {
+        champ_id: me.champion_id,
+        game_mode: details.game_mode,
+        created: details.game_creation,
+        patch: details
+            .game_version
+            .split('.')
+            .take(2)
+            .collect::<Vec<_>>()
+            .join("."),
+        page: RunePage {
+            primary_style_id: stats.perk_primary_style,
+            sub_style_id: stats.perk_sub_style,
+            selected_perk_ids: vec![
+                stats.perk0,
+                stats.perk1,
+                stats.perk2,
+                stats.perk3,
+                stats.perk4,
+                stats.perk5,
+                stats.stat_perk0,
+                stats.stat_perk1,
+                stats.stat_perk2,
+            ],
+            ..Default::default()
+        },
+        spells: (me.spell1_id, me.spell2_id),
+        win: stats.win,
+    })
+}
+
+/// The details of a finished game. Those never change, so they're only fetched once.
+fn game_details(lcuclient: &LCUClient, conn: &Connection, game_id: u64) -> Result<MatchDetails> {
+    let stored: Option<String> = conn
+        .query_row(
+            "select details from match_details where game_id = ?1",
+            params![game_id as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(details) = stored {
+        return Ok(serde_json::from_str(&details)?);
+    }
+    let details = lcuclient
+        .get(&format!("/lol-match-history/v1/games/{}", game_id))?
+        .text()?;
+    let details: MatchDetails = serde_json::from_str(&details)?;
+    conn.execute(
+        "insert into match_details (game_id, details) values (?1, ?2)",
+        params![game_id as i64, serde_json::to_string(&details)?],
+    )?;
+    Ok(details)
+}
+
+/// The player's last `count` games, newest first. Games that can't be read are logged and left
+/// out.
+pub fn recent_games(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    count: usize,
+) -> Result<Vec<PlayedGame>> {
+    let puuid = my_puuid(lcuclient)?;
+    let uri = format!(
+        "/lol-match-history/v1/products/lol/current-summoner/matches?begIndex=0&endIndex={}",
+        count
+    );
+    let history = lcuclient.get(&uri)?.text()?;
+    let history: MatchHistoryResult = serde_json::from_str(&history)?;
+    let mut games = Vec::new();
+    for game in history.games.games.iter().take(count) {
+        // The list only has the player's own summary, the details have the whole game
+        let details = game_details(lcuclient, conn, game.game_id)
+            .and_then(|details| played_game(details, &puuid));
+        match details {
+            Ok(played) => games.push(played),
+            Err(e) => log!("skipping game {}: {}", game.game_id, e),
+        }
+    }
+    games.sort_by_key(|game| std::cmp::Reverse(game.created));
+    Ok(games)
+}
+
+/// A page and spells, with the player's record using them.
+struct Record {
+    game: PlayedGame,
+    wins: u32,
+    games: u32,
+}
+
+/// Pages and spells the player used on the champion in recent games, best record first. Builds
+/// that never won aren't offered.
+pub struct MatchHistory;
+
+impl BuildSource for MatchHistory {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn priority(&self) -> i32 {
+        90
+    }
+
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>> {
+        let lcuclient = context
+            .lcuclient
+            .ok_or_else(|| anyhow!("needs the client running"))?;
+        let mut played: Vec<PlayedGame> =
+            recent_games(lcuclient, context.conn, config().match_history_games)?
+                .into_iter()
+                .filter(|game| game.champ_id == context.champ_id)
+                .collect();
+        // Other modes only count when there's nothing for this one
+        if played
+            .iter()
+            .any(|game| game.game_mode == context.game_mode)
+        {
+            played.retain(|game| game.game_mode == context.game_mode);
+        }
+
+        let mut records: Vec<Record> = Vec::new();
+        for game in played {
+            let same = records.iter_mut().find(|record| {
+                record.game.spells == game.spells && record.game.page.same_runes(&game.page)
+            });
+            let win = u32::from(game.win);
+            match same {
+                // Games are newest first, so the record keeps the latest patch
+                Some(record) => {
+                    record.wins += win;
+                    record.games += 1;
+                }
+                None => records.push(Record {
+                    game,
+                    wins: win,
+                    games: 1,
+                }),
+            }
+        }
+        records.retain(|record| record.wins > 0);
+        records.sort_by(|a, b| {
+            let rate = |record: &Record| record.wins as f64 / record.games as f64;
+            rate(b).total_cmp(&rate(a)).then(b.games.cmp(&a.games))
+        });
+
+        Ok(records
+            .into_iter()
+            .map(|record| {
+                let label = format!("{}-{}", record.wins, record.games - record.wins);
+                let stale = context
+                    .patch
+                    .is_some_and(|patch| patch != record.game.patch);
+                let page = RunePage {
+                    name: config().provider_page_name(&label, "history"),
+                    ..record.game.page
+                };
+                Candidate {
+                    personal_win_rate: Some(record.wins as f64 / record.games as f64),
+                    games: Some(record.games),
+                    last_used: Some(record.game.created / 1000),
+                    stale,
+                    ..Candidate::new(page, record.game.spells, "history")
+                }
+            })
+            .collect())
+    }
+}
+
+/// Save the page from the most recent game on each champion and game mode that has nothing
+/// stored yet. Pages already stored are never replaced, and a broken page falls back to an
+/// older game.
+pub fn backfill(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    count: usize,
+    dry_run: bool,
+) -> Result<()> {
+    let perk_data = perks::get_perk_data(lcuclient)?;
+    let (mut saved, mut skipped) = (0, 0);
+    // Champions and modes that were saved or already had a page. In a dry run nothing gets
+    // stored, so the database can't tell.
+    let mut done = HashSet::new();
+    // Newest first, so the first game for a champion and mode is the one that counts
+    for mut game in recent_games(lcuclient, conn, count)? {
+        let champion = CHAMPIONS.get(&game.champ_id).copied().unwrap_or("?");
+        let key = (game.champ_id, game.game_mode.clone());
+        if done.contains(&key) {
+            skipped += 1;
+            continue;
+        }
+        let stored: bool = conn.query_row(
+            "select count(*) > 0 from rune_pages where champ_id = ?1 and game_mode = ?2",
+            params![game.champ_id as i64, game.game_mode],
+            |row| row.get(0),
+        )?;
+        if stored {
+            done.insert(key);
+            skipped += 1;
+            continue;
+        }
+        match perk_data.check(&mut game.page) {
+            Check::Valid => (),
+            Check::Repaired(changes) => {
+                println!(
+                    "{} ({}): repaired {}",
+                    champion,
+                    game.game_mode,
+                    changes.join(", ")
+                )
+            }
+            Check::Broken(reason) => {
+                println!("{} ({}): skipped, {}", champion, game.game_mode, reason);
+                skipped += 1;
+                continue;
+            }
+        }
+        game.page.name = format!("{} {}", champion, game.game_mode);
+        if dry_run {
+            println!(
+                "would save {} ({}): {:?}",
+                champion, game.game_mode, game.page.selected_perk_ids
+            );
+        } else {
+            save_rune_page(
+                conn,
+                game.champ_id,
+                game.spells,
+                &game.game_mode,
+                &game.page,
+                Some(&game.patch),
+            )?;
+            println!("saved {} ({})", champion, game.game_mode);
+        }
+        done.insert(key);
+        saved += 1;
+    }
+    let verb = if dry_run { "would save" } else { "saved" };
+    println!("{} {} pages, skipped {} games", verb, saved, skipped);
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+
+use crate::config::config;
+use crate::sources::{BuildSource, Context};
+use crate::{add_column, unix_time, Candidate, RunePage, CHAMPIONS};
+
+pub fn setup_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists matchup_pages (
+                champ_id integer not null,
+                game_mode text not null,
+                vs_champ_id integer not null,
+                spell1_id integer not null,
+                spell2_id integer not null,
+                page text not null,
+                patch text,
+                primary key (champ_id, game_mode, vs_champ_id) on conflict replace
+            )",
+        [],
+    )?;
+    add_column(conn, "matchup_pages", "saved_at integer")?;
+    Ok(())
+}
+
+/// Remember the page played against an enemy champion, next to the page saved for the champion.
+pub fn save(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: &str,
+    vs_champ_id: u64,
+    spells: (u64, u64),
+    rune_page: &RunePage,
+    patch: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "insert into matchup_pages
+                (champ_id, game_mode, vs_champ_id, spell1_id, spell2_id, page, patch, saved_at)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            champ_id as i64,
+            game_mode,
+            vs_champ_id as i64,
+            spells.0 as i64,
+            spells.1 as i64,
+            serde_json::to_string(rune_page)?,
+            patch,
+            unix_time()?
+        ],
+    )?;
+    Ok(())
+}
+
+/// Pages the player used before against champions on the enemy team, lane opponent first.
+pub struct Matchups;
+
+impl BuildSource for Matchups {
+    fn name(&self) -> &str {
+        "matchups"
+    }
+
+    fn priority(&self) -> i32 {
+        110
+    }
+
+    fn has_matchups(&self) -> bool {
+        true
+    }
+
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>> {
+        let mut stmt = context.conn.prepare(
+            "select vs_champ_id, spell1_id, spell2_id, page, patch, saved_at from matchup_pages
+                where champ_id = ?1 and game_mode = ?2",
+        )?;
+        let mut rows = stmt.query(params![context.champ_id as i64, context.game_mode])?;
+        let mut candidates = Vec::new();
+        while let Some(row) = rows.next()? {
+            let vs = row.get::<_, i64>(0)? as u64;
+            if !context.opponents.enemies.contains(&vs) {
+                continue;
+            }
+            let spells: (i64, i64) = (row.get(1)?, row.get(2)?);
+            let page: String = row.get(3)?;
+            let saved_patch: Option<String> = row.get(4)?;
+            let saved_at: Option<i64> = row.get(5)?;
+            let mut page: RunePage = serde_json::from_str(&page)?;
+            let label = format!("vs {}", CHAMPIONS.get(&vs).copied().unwrap_or("?"));
+            page.name = config().saved_page_name(CHAMPIONS[&context.champ_id], &label);
+            candidates.push(Candidate {
+                vs: Some(vs),
+                last_used: saved_at.map(|saved_at| saved_at as u64),
+                stale: matches!((saved_patch.as_deref(), context.patch),
+                    (Some(saved), Some(patch)) if saved != patch),
+                ..Candidate::new(page, (spells.0 as u64, spells.1 as u64), "matchups")
+            });
+        }
+        Ok(candidates)
+    }
+}
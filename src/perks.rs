@@ -0,0 +1,341 @@
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::lcu::LCUClient;
+use crate::{RunePage, CHAMPIONS};
+
+/*
+ * Perks that were removed from the game, and the perk in the same slot that replaced them.
+ * Only used when the old id is no longer known to the client.
+ */
+static REMAP: &[(i64, i64)] = &[
+    (8120, 8137), // Ghost Poro -> Sixth Sense
+    (8136, 8141), // Zombie Ward -> Deep Ward
+    (8138, 8140), // Eyeball Collection -> Grisly Mementos
+];
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Perk {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Style {
+    id: i64,
+    slots: Vec<Slot>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Slot {
+    #[serde(rename = "type")]
+    kind: String,
+    perks: Vec<i64>,
+}
+
+impl Slot {
+    fn is_stat_mod(&self) -> bool {
+        self.kind == "kStatMod"
+    }
+
+    fn is_keystone(&self) -> bool {
+        self.kind == "kKeyStone"
+    }
+}
+
+pub struct PerkData {
+    perks: HashSet<i64>,
+    styles: HashMap<i64, Style>,
+}
+
+#[derive(Debug)]
+pub enum Check {
+    Valid,
+    /// The page was changed to be valid, the strings say what was changed
+    Repaired(Vec<String>),
+    /// The page can't be made valid without guessing runes
+    Broken(String),
+}
+
+pub fn get_perk_data(lcuclient: &LCUClient) -> Result<PerkData> {
+    let perks = lcuclient.get("/lol-perks/v1/perks")?.text()?;
+    let perks: Vec<Perk> = serde_json::from_str(&perks)?;
+    let styles = lcuclient.get("/lol-perks/v1/styles")?.text()?;
+    let styles: Vec<Style> = serde_json::from_str(&styles)?;
+    if perks.is_empty() || styles.is_empty() {
+        return Err(anyhow!("no perk data yet"));
+    }
+    Ok(PerkData {
+        perks: perks.into_iter().map(|perk| perk.id).collect(),
+        styles: styles.into_iter().map(|style| (style.id, style)).collect(),
+    })
+}
+
+/// Take one perk out of `perks` that fits `slot`.
+fn take_from_slot(perks: &mut Vec<i64>, slot: &Slot) -> Option<i64> {
+    let index = perks.iter().position(|perk| slot.perks.contains(perk))?;
+    Some(perks.remove(index))
+}
+
+/// Match shards to stat slots. Shards can fit more than one slot, so try every order rather
+/// than taking the first fit.
+fn match_shards(shards: &[i64], slots: &[&Slot]) -> Option<Vec<i64>> {
+    if slots.is_empty() {
+        return Some(Vec::new());
+    }
+    for (index, shard) in shards.iter().enumerate() {
+        if slots[0].perks.contains(shard) {
+            let mut rest = shards.to_vec();
+            rest.remove(index);
+            if let Some(mut matched) = match_shards(&rest, &slots[1..]) {
+                matched.insert(0, *shard);
+                return Some(matched);
+            }
+        }
+    }
+    None
+}
+
+impl PerkData {
+    /// Check a page against the current perks, fixing what can be fixed without changing the
+    /// player's choice of runes. Perks end up in slot order: primary, secondary, shards.
+    pub fn check(&self, page: &mut RunePage) -> Check {
+        let mut changes = Vec::new();
+        let primary = match self.styles.get(&page.primary_style_id) {
+            Some(style) => style,
+            None => return Check::Broken(format!("unknown style {}", page.primary_style_id)),
+        };
+        let sub = match self.styles.get(&page.sub_style_id) {
+            Some(style) if style.id != primary.id => style,
+            _ => return Check::Broken(format!("bad secondary style {}", page.sub_style_id)),
+        };
+
+        let mut perks: Vec<i64> = page
+            .selected_perk_ids
+            .iter()
+            .map(|perk| {
+                if self.perks.contains(perk) {
+                    return *perk;
+                }
+                match REMAP.iter().find(|(old, _)| old == perk) {
+                    Some((old, new)) => {
+                        changes.push(format!("replaced retired perk {} with {}", old, new));
+                        *new
+                    }
+                    None => *perk,
+                }
+            })
+            .collect();
+
+        let mut fixed = Vec::new();
+        for slot in primary.slots.iter().filter(|slot| !slot.is_stat_mod()) {
+            match take_from_slot(&mut perks, slot) {
+                Some(perk) => fixed.push(perk),
+                None => return Check::Broken("missing a primary rune".to_string()),
+            }
+        }
+        let mut sub_slots: Vec<&Slot> = sub
+            .slots
+            .iter()
+            .filter(|slot| !slot.is_stat_mod() && !slot.is_keystone())
+            .collect();
+        for _ in 0..2 {
+            let found = sub_slots
+                .iter()
+                .enumerate()
+                .find_map(|(index, slot)| Some((index, take_from_slot(&mut perks, slot)?)));
+            match found {
+                Some((index, perk)) => {
+                    sub_slots.remove(index);
+                    fixed.push(perk);
+                }
+                None => return Check::Broken("missing a secondary rune".to_string()),
+            }
+        }
+
+        let stat_slots: Vec<&Slot> = primary
+            .slots
+            .iter()
+            .filter(|slot| slot.is_stat_mod())
+            .collect();
+        match match_shards(&perks, &stat_slots) {
+            Some(shards) => {
+                if perks.len() > shards.len() {
+                    changes.push("dropped extra perks".to_string());
+                }
+                fixed.extend(shards);
+            }
+            None => {
+                // Shards change more often than runes, and are easy to pick, so just reset them
+                changes.push(format!("reset shards {:?}", perks));
+                for slot in &stat_slots {
+                    match take_from_slot(&mut perks, slot).or(slot.perks.first().copied()) {
+                        Some(perk) => fixed.push(perk),
+                        None => return Check::Broken("empty shard slot".to_string()),
+                    }
+                }
+            }
+        }
+
+        page.selected_perk_ids = fixed;
+        if changes.is_empty() {
+            return Check::Valid;
+        }
+        Check::Repaired(changes)
+    }
+}
+
+/// Check every stored page, and write back repaired ones if `repair` is set and it isn't a
+/// dry run.
+pub fn verify_db(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    repair: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let repair = repair && !dry_run;
+    let data = get_perk_data(lcuclient)?;
+    let (mut valid, mut repaired, mut broken) = (0, 0, 0);
+    for table in ["rune_pages", "rune_page_history"] {
+        let mut stmt = conn.prepare(&format!(
+            "select champ_id, game_mode, page from {} order by champ_id, game_mode",
+            table
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                let champ_id: i64 = row.get(0)?;
+                Ok((champ_id, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (champ_id, game_mode, page) in rows {
+            let champion = CHAMPIONS.get(&(champ_id as u64)).copied().unwrap_or("?");
+            let mut page: RunePage = serde_json::from_str(&page)?;
+            match data.check(&mut page) {
+                Check::Valid => valid += 1,
+                Check::Repaired(changes) => {
+                    println!("{} ({}): {}", champion, game_mode, changes.join(", "));
+                    repaired += 1;
+                    if repair {
+                        conn.execute(
+                            &format!(
+                                "update {} set page = ?3 where champ_id = ?1 and game_mode = ?2",
+                                table
+                            ),
+                            params![champ_id, game_mode, serde_json::to_string(&page)?],
+                        )?;
+                    }
+                }
+                Check::Broken(reason) => {
+                    println!("{} ({}): broken, {}", champion, game_mode, reason);
+                    broken += 1;
+                }
+            }
+        }
+    }
+    let fixed = if repair { "repaired" } else { "repairable" };
+    println!("{} valid, {} {}, {} broken", valid, repaired, fixed, broken);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(kind: &str, perks: &[i64]) -> Slot {
+        Slot {
+            kind: kind.to_string(),
+            perks: perks.to_vec(),
+        }
+    }
+
+    #[test]
+    fn match_shards_tries_every_order() {
+        let (first, second) = (slot("kStatMod", &[5008, 5001]), slot("kStatMod", &[5008]));
+        // Taking 5008 for the first slot would leave nothing for the second
+        assert_eq!(
+            match_shards(&[5008, 5001], &[&first, &second]),
+            Some(vec![5001, 5008])
+        );
+        assert_eq!(match_shards(&[5001, 5001], &[&first, &second]), None);
+        assert_eq!(match_shards(&[5008], &[]), Some(vec![]));
+    }
+
+    #[test]
+    fn check_repairs_and_rejects_pages() {
+        let domination = Style {
+            id: 8100,
+            slots: vec![
+                slot("kKeyStone", &[8112, 8128]),
+                slot("kMixedRegularSplashable", &[8126, 8139]),
+                slot("kMixedRegularSplashable", &[8137, 8140]),
+                slot("kMixedRegularSplashable", &[8135, 8105]),
+                slot("kStatMod", &[5008, 5005]),
+                slot("kStatMod", &[5008, 5001]),
+                slot("kStatMod", &[5011, 5001]),
+            ],
+        };
+        let sorcery = Style {
+            id: 8200,
+            slots: vec![
+                slot("kKeyStone", &[8214, 8229]),
+                slot("kMixedRegularSplashable", &[8226, 8275]),
+                slot("kMixedRegularSplashable", &[8210, 8234]),
+                slot("kMixedRegularSplashable", &[8237, 8232]),
+            ],
+        };
+        let data = PerkData {
+            perks: [
+                8112, 8128, 8126, 8139, 8137, 8140, 8135, 8105, 8214, 8229, 8226, 8275, 8210, 8234,
+                8237, 8232, 5008, 5005, 5001, 5011,
+            ]
+            .into_iter()
+            .collect(),
+            styles: [(8100, domination), (8200, sorcery)].into_iter().collect(),
+        };
+        let page = |perks: &[i64]| RunePage {
+            primary_style_id: 8100,
+            sub_style_id: 8200,
+            selected_perk_ids: perks.to_vec(),
+            ..Default::default()
+        };
+
+        // Out of order is fine, the perks come back in slot order
+        let mut valid = page(&[8226, 8112, 8139, 8140, 8105, 8237, 5008, 5001, 5011]);
+        assert!(matches!(data.check(&mut valid), Check::Valid));
+        assert_eq!(
+            valid.selected_perk_ids,
+            vec![8112, 8139, 8140, 8105, 8226, 8237, 5008, 5001, 5011]
+        );
+
+        // Eyeball Collection was retired for Grisly Mementos
+        let mut retired = page(&[8112, 8139, 8138, 8105, 8226, 8237, 5008, 5001, 5011]);
+        match data.check(&mut retired) {
+            Check::Repaired(changes) => {
+                assert_eq!(changes, vec!["replaced retired perk 8138 with 8140"])
+            }
+            other => panic!("expected a repair, got {:?}", other),
+        }
+        assert_eq!(retired.selected_perk_ids[2], 8140);
+
+        // Shards that fit no slot are reset to the first shard of each slot
+        let mut shards = page(&[8112, 8139, 8140, 8105, 8226, 8237, 5011, 5011, 5011]);
+        assert!(matches!(data.check(&mut shards), Check::Repaired(_)));
+        assert_eq!(shards.selected_perk_ids[6..], [5008, 5008, 5011]);
+
+        let mut no_keystone = page(&[8139, 8140, 8105, 8226, 8237, 5008, 5001, 5011]);
+        assert!(matches!(
+            data.check(&mut no_keystone),
+            Check::Broken(reason) if reason == "missing a primary rune"
+        ));
+
+        let mut same_styles = RunePage {
+            sub_style_id: 8100,
+            ..page(&[])
+        };
+        assert!(matches!(data.check(&mut same_styles), Check::Broken(_)));
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use crate::config::config;
+use crate::{get_mobalytics_builds, setup_sqlite, sources, CHAMPIONS};
+
+enum Request {
+    /// Likely picks in champ select, these go first
+    ChampSelect(u64),
+    /// The player's champions, fetched while waiting in the lobby
+    Pool(u64),
+}
+
+/// Fetches provider builds into the cache from a background thread, so champ select doesn't wait
+/// on the provider. Each champion is only asked for once until `reset`.
+pub struct Prefetcher {
+    sender: Sender<Request>,
+    sent: HashSet<u64>,
+    pooled: HashSet<u64>,
+}
+
+impl Prefetcher {
+    /// Fetch likely picks ahead of anything from the pool.
+    pub fn request(&mut self, champ_ids: impl IntoIterator<Item = u64>) {
+        for champ_id in champ_ids {
+            if CHAMPIONS.contains_key(&champ_id) && self.sent.insert(champ_id) {
+                let _ = self.sender.send(Request::ChampSelect(champ_id));
+            }
+        }
+    }
+
+    pub fn request_pool(&mut self, champ_ids: impl IntoIterator<Item = u64>) {
+        for champ_id in champ_ids {
+            if CHAMPIONS.contains_key(&champ_id)
+                && !self.sent.contains(&champ_id)
+                && self.pooled.insert(champ_id)
+            {
+                let _ = self.sender.send(Request::Pool(champ_id));
+            }
+        }
+    }
+
+    /// Let champions be asked for again, the cache decides if they're still fresh.
+    pub fn reset(&mut self) {
+        self.sent.clear();
+        self.pooled.clear();
+    }
+}
+
+/// Take requests off the channel until it's closed, always fetching champ select picks before
+/// the pool.
+fn work(conn: Connection, receiver: Receiver<Request>) {
+    let (mut picks, mut pool) = (VecDeque::new(), VecDeque::new());
+    loop {
+        // Only wait for a request when there's nothing left to do
+        let first = if picks.is_empty() && pool.is_empty() {
+            match receiver.recv() {
+                Ok(request) => Some(request),
+                Err(_) => return,
+            }
+        } else {
+            None
+        };
+        for request in first.into_iter().chain(receiver.try_iter()) {
+            match request {
+                Request::ChampSelect(champ_id) => {
+                    pool.retain(|pooled| *pooled != champ_id);
+                    picks.push_back(champ_id);
+                }
+                Request::Pool(champ_id) => pool.push_back(champ_id),
+            }
+        }
+        let Some(champ_id) = picks.pop_front().or_else(|| pool.pop_front()) else {
+            continue;
+        };
+        if let Err(e) = get_mobalytics_builds(&conn, champ_id) {
+            log!(
+                "couldn't prefetch builds for {}: {}",
+                CHAMPIONS[&champ_id],
+                e
+            );
+        }
+    }
+}
+
+/// Start the prefetch thread. Returns None when prefetching is off, or no source would use it.
+pub fn start() -> Result<Option<Prefetcher>> {
+    let uses_mobalytics = sources::registry()
+        .enabled()
+        .iter()
+        .any(|source| source.name() == "mobalytics");
+    if !config().prefetch || !uses_mobalytics {
+        return Ok(None);
+    }
+    let conn = setup_sqlite()?;
+    let (sender, receiver) = channel();
+    thread::spawn(move || work(conn, receiver));
+    Ok(Some(Prefetcher {
+        sender,
+        sent: HashSet::new(),
+        pooled: HashSet::new(),
+    }))
+}
+
+/// The champions the player has pages stored for, most recently saved first, up to
+/// `prefetch_pool` of them.
+pub fn pool(conn: &Connection) -> Result<Vec<u64>> {
+    let mut stmt = conn.prepare(
+        "select champ_id from rune_pages group by champ_id
+            order by max(coalesce(saved_at, 0)) desc, champ_id limit ?1",
+    )?;
+    let champs = stmt
+        .query_map(params![config().prefetch_pool as i64], |row| {
+            row.get::<_, i64>(0)
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(champs.into_iter().map(|champ_id| champ_id as u64).collect())
+}
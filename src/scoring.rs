@@ -0,0 +1,236 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{config, Weights};
+use crate::sources::Context;
+use crate::Candidate;
+
+/// The lower bound of the 95% Wilson score interval for a win rate over `games` games. Few
+/// games give a wide interval, so a lucky streak doesn't outrank a proven build.
+fn wilson_lower_bound(rate: f64, games: u32) -> f64 {
+    if games == 0 {
+        return 0.0;
+    }
+    let z = 1.96_f64;
+    let n = f64::from(games);
+    let spread = z * (rate * (1.0 - rate) / n + z * z / (4.0 * n * n)).sqrt();
+    (rate + z * z / (2.0 * n) - spread) / (1.0 + z * z / n)
+}
+
+/// Why a provider build is left out, if it is. Builds from sources that don't give these
+/// numbers are always kept.
+pub fn below_threshold(candidate: &Candidate) -> Option<String> {
+    // Only provider builds have a win rate
+    candidate.win_rate?;
+    let config = config();
+    if let Some(games) = candidate.games.filter(|games| *games < config.min_games) {
+        return Some(format!(
+            "{} games, min_games is {}",
+            games, config.min_games
+        ));
+    }
+    if let Some(pick_rate) = candidate.pick_rate.filter(|rate| *rate < config.min_pick_rate) {
+        return Some(format!(
+            "picked {:.2}%, min_pick_rate is {}",
+            pick_rate * 100.0,
+            config.min_pick_rate
+        ));
+    }
+    None
+}
+
+/// What each signal adds to the score. Signals are scaled to about -1 to 1 before they're
+/// weighted, and a missing signal counts 0.
+fn score_parts(
+    candidate: &Candidate,
+    source_rank: f64,
+    context: &Context,
+    now: u64,
+    weights: &Weights,
+) -> Vec<(&'static str, f64)> {
+    // Win rates are compared to a coin flip, so a bad record counts against a page
+    let win_rate = |rate: Option<f64>| {
+        rate.map_or(0.0, |rate| {
+            let rate = match candidate.games {
+                Some(games) => wilson_lower_bound(rate, games),
+                None => rate,
+            };
+            (rate - 0.5) * 2.0
+        })
+    };
+    let sample_size = candidate
+        .games
+        .map_or(0.0, |games| (f64::from(games) + 1.0).log10() / 3.0)
+        .min(1.0);
+    let recency = candidate.last_used.map_or(0.0, |last_used| {
+        let days = now.saturating_sub(last_used) as f64 / 86400.0;
+        0.5_f64.powf(days / weights.recency_half_life_days)
+    });
+    let role = match (context.position, candidate.role) {
+        (Some(position), Some(role)) if position == role => 1.0,
+        (Some(_), Some(_)) => -1.0,
+        _ => 0.0,
+    };
+    let matchup = match context.opponents.rank(candidate.vs) {
+        0 => 1.0,
+        1 => 0.5,
+        2 => 0.0,
+        _ => -1.0,
+    };
+    vec![
+        ("source", weights.source * source_rank),
+        ("win rate", weights.win_rate * win_rate(candidate.win_rate)),
+        (
+            "pick rate",
+            weights.pick_rate * candidate.pick_rate.unwrap_or(0.0),
+        ),
+        ("sample size", weights.sample_size * sample_size),
+        (
+            "personal win rate",
+            weights.personal_win_rate * win_rate(candidate.personal_win_rate),
+        ),
+        ("recency", weights.recency * recency),
+        (
+            "pinned",
+            weights.pinned * f64::from(u8::from(candidate.pinned)),
+        ),
+        ("role", weights.role * role),
+        ("matchup", weights.matchup * matchup),
+    ]
+}
+
+/// Score the candidates and sort them best first. `sources` are the enabled source names in
+/// order, earlier ones count for more. Pages from older patches might have removed runes, so
+/// they always go last, to only be used if there's room left. Otherwise pinned pages go first,
+/// whatever their score.
+pub fn rank(candidates: &mut [Candidate], sources: &[&str], context: &Context) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    for candidate in candidates.iter_mut() {
+        let source_rank = sources
+            .iter()
+            .position(|source| *source == candidate.source)
+            .map_or(0.0, |index| 1.0 - index as f64 / sources.len() as f64);
+        candidate.score_parts =
+            score_parts(candidate, source_rank, context, now, &config().weights);
+        candidate.score = candidate.score_parts.iter().map(|(_, part)| part).sum();
+    }
+    candidates.sort_by(|a, b| {
+        a.stale
+            .cmp(&b.stale)
+            .then(b.pinned.cmp(&a.pinned))
+            .then(b.score.total_cmp(&a.score))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::Opponents;
+    use crate::RunePage;
+    use rusqlite::Connection;
+
+    #[test]
+    fn wilson_lower_bound_favours_more_games() {
+        let few = wilson_lower_bound(7.0 / 12.0, 12);
+        let many = wilson_lower_bound(0.52, 1000);
+        assert!((few - 0.3195).abs() < 0.0001, "{}", few);
+        assert!((many - 0.4890).abs() < 0.0001, "{}", many);
+        assert!(many > few);
+        assert_eq!(wilson_lower_bound(0.6, 0), 0.0);
+    }
+
+    #[test]
+    fn below_threshold_only_drops_provider_builds() {
+        let page = |name: &str| RunePage {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let saved = Candidate {
+            games: Some(3),
+            ..Candidate::new(page("saved"), (4, 14), "saved")
+        };
+        assert_eq!(below_threshold(&saved), None);
+
+        let few_games = Candidate {
+            win_rate: Some(0.6),
+            games: Some(10),
+            ..Candidate::new(page("few games"), (4, 14), "mobalytics")
+        };
+        assert_eq!(
+            below_threshold(&few_games).as_deref(),
+            Some("10 games, min_games is 50")
+        );
+
+        let rare = Candidate {
+            win_rate: Some(0.6),
+            pick_rate: Some(0.005),
+            games: Some(500),
+            ..Candidate::new(page("rare"), (4, 14), "mobalytics")
+        };
+        assert_eq!(
+            below_threshold(&rare).as_deref(),
+            Some("picked 0.50%, min_pick_rate is 0.01")
+        );
+
+        let common = Candidate {
+            win_rate: Some(0.5),
+            pick_rate: Some(0.2),
+            games: Some(500),
+            ..Candidate::new(page("common"), (4, 14), "mobalytics")
+        };
+        assert_eq!(below_threshold(&common), None);
+    }
+
+    #[test]
+    fn rank_puts_pinned_first_and_stale_last() {
+        let conn = Connection::open_in_memory().unwrap();
+        let opponents = Opponents::default();
+        let context = Context {
+            lcuclient: None,
+            conn: &conn,
+            champ_id: 103,
+            game_mode: "CLASSIC",
+            position: None,
+            patch: None,
+            opponents: &opponents,
+        };
+        let page = |name: &str| RunePage {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut candidates = vec![
+            Candidate {
+                pinned: true,
+                stale: true,
+                ..Candidate::new(page("old pinned"), (4, 14), "saved")
+            },
+            Candidate {
+                win_rate: Some(0.45),
+                games: Some(2000),
+                ..Candidate::new(page("losing"), (4, 14), "mobalytics")
+            },
+            Candidate {
+                win_rate: Some(0.56),
+                personal_win_rate: Some(0.7),
+                games: Some(5000),
+                ..Candidate::new(page("winning"), (4, 14), "mobalytics")
+            },
+            Candidate {
+                pinned: true,
+                win_rate: Some(0.0),
+                personal_win_rate: Some(0.0),
+                games: Some(1000),
+                ..Candidate::new(page("pinned"), (4, 14), "mobalytics")
+            },
+        ];
+        rank(&mut candidates, &["saved", "mobalytics"], &context);
+        let names: Vec<&str> = candidates
+            .iter()
+            .map(|candidate| candidate.page.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["pinned", "winning", "losing", "old pinned"]);
+        // The pinned page is first even though it scores lower
+        assert!(candidates[0].score < candidates[1].score);
+    }
+}
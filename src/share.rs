@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::*;
+use rusqlite::{params, Connection};
+
+use crate::lcu::LCUClient;
+use crate::{
+    check_or_make_space, save_rune_page, set_rune_page, set_spells, RunePage, CHAMPIONS,
+};
+
+/*
+ * A share code is "RP" followed by the URL-safe base64 of:
+ *
+ *   version     u8
+ *   champ_id    u16   (0 if the code isn't tied to a champion)
+ *   primary     u16
+ *   sub         u16
+ *   perk count  u8
+ *   perks       u16 * perk count
+ *   spell1      u16
+ *   spell2      u16
+ *   checksum    u16   (Fletcher-16 over everything above)
+ *
+ * All integers are big endian. A full page of nine perks comes out at 45 characters.
+ */
+static PREFIX: &str = "RP";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct SharedPage {
+    pub champ_id: u64,
+    pub page: RunePage,
+    pub spells: (u64, u64),
+}
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for byte in data {
+        sum1 = (sum1 + *byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    (sum2 << 8) | sum1
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: i64, what: &str) -> Result<()> {
+    let value: u16 = value
+        .try_into()
+        .map_err(|_| anyhow!("{} {} doesn't fit in a share code", what, value))?;
+    bytes.extend_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+pub fn encode(shared: &SharedPage) -> Result<String> {
+    let page = &shared.page;
+    let mut bytes = vec![VERSION];
+    push_u16(&mut bytes, shared.champ_id as i64, "champion id")?;
+    push_u16(&mut bytes, page.primary_style_id, "style id")?;
+    push_u16(&mut bytes, page.sub_style_id, "style id")?;
+    let count: u8 = page
+        .selected_perk_ids
+        .len()
+        .try_into()
+        .context("too many perks for a share code")?;
+    bytes.push(count);
+    for perk in &page.selected_perk_ids {
+        push_u16(&mut bytes, *perk, "perk id")?;
+    }
+    push_u16(&mut bytes, shared.spells.0 as i64, "spell id")?;
+    push_u16(&mut bytes, shared.spells.1 as i64, "spell id")?;
+    let checksum = fletcher16(&bytes);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+    Ok(format!("{}{}", PREFIX, BASE64_URL_SAFE_NO_PAD.encode(bytes)))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        let (first, rest) = self.bytes.split_first().context("share code is truncated")?;
+        self.bytes = rest;
+        Ok(*first)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+}
+
+pub fn decode(code: &str) -> Result<SharedPage> {
+    let data = code
+        .trim()
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| anyhow!("not a share code, should start with {}", PREFIX))?;
+    let bytes = BASE64_URL_SAFE_NO_PAD
+        .decode(data)
+        .context("share code is not valid base64")?;
+    if bytes.len() < 3 {
+        return Err(anyhow!("share code is truncated"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 2);
+    if fletcher16(body).to_be_bytes() != checksum {
+        return Err(anyhow!("share code checksum mismatch, was it copied completely?"));
+    }
+
+    let mut reader = Reader { bytes: body };
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(anyhow!("unsupported share code version {}", version));
+    }
+    let champ_id = reader.u16()? as u64;
+    let primary_style_id = reader.u16()? as i64;
+    let sub_style_id = reader.u16()? as i64;
+    let count = reader.u8()?;
+    let selected_perk_ids = (0..count)
+        .map(|_| Ok(reader.u16()? as i64))
+        .collect::<Result<Vec<i64>>>()?;
+    let spells = (reader.u16()? as u64, reader.u16()? as u64);
+    if !reader.bytes.is_empty() {
+        return Err(anyhow!("share code has trailing data"));
+    }
+
+    let champion = CHAMPIONS.get(&champ_id).copied().unwrap_or("Any champion");
+    let page = RunePage {
+        name: format!("{} (shared)", champion),
+        primary_style_id,
+        sub_style_id,
+        selected_perk_ids,
+        ..Default::default()
+    };
+    Ok(SharedPage {
+        champ_id,
+        page,
+        spells,
+    })
+}
+
+/// Look up the stored page for a champion, preferring `game_mode` if given.
+pub fn stored_page(
+    conn: &Connection,
+    champ_id: u64,
+    game_mode: Option<&str>,
+) -> Result<Option<SharedPage>> {
+    let mut stmt = conn.prepare(
+        "select spell1_id, spell2_id, page from rune_pages
+            where champ_id = ?1 and (?2 is null or game_mode = ?2)",
+    )?;
+    let mut rows = stmt.query(params![champ_id as i64, game_mode])?;
+    if let Some(row) = rows.next()? {
+        let spells: (i64, i64) = (row.get(0)?, row.get(1)?);
+        let page: String = row.get(2)?;
+        return Ok(Some(SharedPage {
+            champ_id,
+            page: serde_json::from_str(&page)?,
+            spells: (spells.0 as u64, spells.1 as u64),
+        }));
+    }
+    Ok(None)
+}
+
+pub fn store(conn: &Connection, shared: &SharedPage, game_mode: &str, dry_run: bool) -> Result<()> {
+    if !CHAMPIONS.contains_key(&shared.champ_id) {
+        return Err(anyhow!("share code isn't for a specific champion, can't store it"));
+    }
+    if dry_run {
+        println!(
+            "would store page for {} ({})",
+            CHAMPIONS[&shared.champ_id], game_mode
+        );
+        return Ok(());
+    }
+    save_rune_page(conn, shared.champ_id, shared.spells, game_mode, &shared.page, None)?;
+    println!(
+        "stored page for {} ({})",
+        CHAMPIONS[&shared.champ_id], game_mode
+    );
+    Ok(())
+}
+
+pub fn push_to_client(lcuclient: &LCUClient, shared: &SharedPage, dry_run: bool) -> Result<()> {
+    if check_or_make_space(lcuclient, dry_run)? == 0 {
+        return Err(anyhow!("no room for another rune page"));
+    }
+    if dry_run {
+        println!("would create {}", shared.page.name);
+        return Ok(());
+    }
+    set_rune_page(lcuclient, &shared.page)?;
+    // Only works during champ select, so don't care if it fails
+    let _ = set_spells(lcuclient, shared.champ_id, shared.spells, dry_run);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let shared = SharedPage {
+            champ_id: 103,
+            page: RunePage {
+                primary_style_id: 8100,
+                sub_style_id: 8300,
+                selected_perk_ids: vec![8112, 8139, 8138, 8135, 8345, 8347, 5008, 5008, 5002],
+                ..Default::default()
+            },
+            spells: (4, 14),
+        };
+        let code = encode(&shared).unwrap();
+        assert_eq!(code.len(), 45);
+        let decoded = decode(&code).unwrap();
+        assert_eq!(decoded.champ_id, 103);
+        assert_eq!(decoded.page.primary_style_id, 8100);
+        assert_eq!(decoded.page.sub_style_id, 8300);
+        assert_eq!(decoded.page.selected_perk_ids, shared.page.selected_perk_ids);
+        assert_eq!(decoded.spells, (4, 14));
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let shared = SharedPage {
+            champ_id: 0,
+            page: RunePage {
+                primary_style_id: 8000,
+                sub_style_id: 8200,
+                selected_perk_ids: vec![8010, 9111, 9104, 8299, 8226, 8210, 5005, 5008, 5002],
+                ..Default::default()
+            },
+            spells: (4, 12),
+        };
+        let code = encode(&shared).unwrap();
+        // Change one character in the middle, which lands in a perk id
+        let mut chars: Vec<char> = code.chars().collect();
+        chars[20] = if chars[20] == 'A' { 'B' } else { 'A' };
+        let broken: String = chars.into_iter().collect();
+        let err = decode(&broken).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        assert!(decode("XX1234").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lcu::LCUClient;
+use crate::{delete_page, set_rune_page, RunePage};
+
+pub fn setup_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "create table if not exists snapshots (
+                id integer primary key autoincrement,
+                name text,
+                created integer not null,
+                pages text not null
+            )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn get_pages(lcuclient: &LCUClient) -> Result<Vec<RunePage>> {
+    let pages = lcuclient.get("/lol-perks/v1/pages")?.text()?;
+    Ok(serde_json::from_str(&pages)?)
+}
+
+/// Store all of the client's editable pages.
+pub fn take(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    name: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut pages: Vec<RunePage> = get_pages(lcuclient)?
+        .into_iter()
+        .filter(|page| page.is_deletable)
+        .collect();
+    pages.sort_unstable_by_key(|page| page.order);
+    if dry_run {
+        println!("would save a snapshot with {} pages", pages.len());
+        return Ok(());
+    }
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    conn.execute(
+        "insert into snapshots (name, created, pages) values (?1, ?2, ?3)",
+        params![name, created as i64, serde_json::to_string(&pages)?],
+    )?;
+    let id = conn.last_insert_rowid();
+    println!("saved snapshot {} with {} pages", id, pages.len());
+    Ok(())
+}
+
+pub fn list(conn: &Connection) -> Result<()> {
+    let mut stmt =
+        conn.prepare("select id, name, created, pages from snapshots order by id")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let name: Option<String> = row.get(1)?;
+        let created: i64 = row.get(2)?;
+        let pages: String = row.get(3)?;
+        let pages: Vec<RunePage> = serde_json::from_str(&pages)?;
+        println!(
+            "{:>4}  {:<20} created {}  {} pages",
+            id,
+            name.unwrap_or_default(),
+            created,
+            pages.len()
+        );
+        for page in pages {
+            let current = if page.current { "*" } else { " " };
+            println!("       {} {}", current, page.name);
+        }
+    }
+    Ok(())
+}
+
+fn find(conn: &Connection, snapshot: &str) -> Result<Vec<RunePage>> {
+    // Names can be reused, so a name restores the most recent snapshot with it.
+    let pages: Option<String> = conn
+        .query_row(
+            "select pages from snapshots where cast(id as text) = ?1 or name = ?1
+                order by id desc limit 1",
+            params![snapshot],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let pages = pages.ok_or_else(|| anyhow!("no snapshot named {}", snapshot))?;
+    Ok(serde_json::from_str(&pages)?)
+}
+
+/// Replace the client's editable pages with those in the snapshot. With `dry_run`, only print
+/// what would be deleted and created.
+pub fn restore(
+    lcuclient: &LCUClient,
+    conn: &Connection,
+    snapshot: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let mut pages = find(conn, snapshot)?;
+
+    let max_pages = lcuclient.get("/lol-perks/v1/inventory")?.text()?;
+    let max_pages = serde_json::from_str::<serde_json::Value>(&max_pages)?["ownedPageCount"]
+        .as_u64()
+        .context("no ownedPageCount in inventory")? as usize;
+    if pages.len() > max_pages {
+        println!(
+            "snapshot has {} pages, but there's only room for {}",
+            pages.len(),
+            max_pages
+        );
+        // Make sure the current page survives the cut, the rest goes by order
+        pages.sort_by_key(|page| (!page.current, page.order));
+        pages.truncate(max_pages);
+        pages.sort_by_key(|page| page.order);
+    }
+
+    // Restoring the wrong snapshot shouldn't lose anything either.
+    take(lcuclient, conn, Some("before restore"), dry_run)?;
+
+    for page in get_pages(lcuclient)?
+        .iter()
+        .filter(|page| page.is_deletable)
+    {
+        if dry_run {
+            println!("would delete {} [id:{}]", page.name, page.id);
+            continue;
+        }
+        println!("deleting {} [id:{}]", page.name, page.id);
+        delete_page(lcuclient, page)?;
+    }
+    if dry_run {
+        for page in &pages {
+            println!("would create {}", page.name);
+        }
+        return Ok(());
+    }
+
+    let mut current_id = None;
+    for page in &pages {
+        let id = set_rune_page(lcuclient, page)?;
+        if page.current {
+            current_id = Some(id);
+        }
+    }
+    if let Some(id) = current_id {
+        lcuclient.put("/lol-perks/v1/currentpage", id.to_string())?;
+    }
+    println!("restored {} pages", pages.len());
+    Ok(())
+}
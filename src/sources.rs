@@ -0,0 +1,210 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use std::sync::OnceLock;
+
+use crate::config::config;
+use crate::folder::Folder;
+use crate::lcu::LCUClient;
+use crate::match_history::MatchHistory;
+use crate::matchups::Matchups;
+use crate::scoring;
+use crate::{get_local_info, get_mobalytics_info, Candidate, Dropped};
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// What a source gets to go on when looking for pages.
+pub struct Context<'a> {
+    /// Not there when the client isn't running
+    pub lcuclient: Option<&'a LCUClient>,
+    pub conn: &'a Connection,
+    pub champ_id: u64,
+    pub game_mode: &'a str,
+    /// The player's role in champ select, as given by `normalize_role`
+    pub position: Option<&'a str>,
+    pub patch: Option<&'a str>,
+    pub opponents: &'a Opponents,
+}
+
+/// Who the player is up against, as far as champ select shows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Opponents {
+    /// Enemy champions that have been picked
+    pub enemies: Vec<u64>,
+    /// The enemy with the player's role, once both roles are known
+    pub laner: Option<u64>,
+}
+
+impl Opponents {
+    /// Builds against the lane opponent come first, then builds against the rest of the enemy
+    /// team, then builds for any matchup.
+    pub fn rank(&self, vs: Option<u64>) -> u8 {
+        match vs {
+            Some(vs) if Some(vs) == self.laner => 0,
+            Some(vs) if self.enemies.contains(&vs) => 1,
+            None => 2,
+            Some(_) => 3,
+        }
+    }
+}
+
+/// Somewhere rune pages come from.
+pub trait BuildSource: Send + Sync {
+    /// Used in the config's `sources` list, and shown with the pages
+    fn name(&self) -> &str;
+    /// Without a `sources` list in the config, sources go from highest to lowest priority
+    fn priority(&self) -> i32;
+    /// Pages for the champion, best first. Pages from an older patch should be marked stale.
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>>;
+    /// Has builds against specific enemies, so pages are worth ranking again when they're picked
+    fn has_matchups(&self) -> bool {
+        false
+    }
+}
+
+struct Saved;
+
+impl BuildSource for Saved {
+    fn name(&self) -> &str {
+        "saved"
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>> {
+        let (mut fresh, stale) = get_local_info(
+            context.conn,
+            context.champ_id,
+            context.game_mode,
+            context.patch,
+        )?;
+        fresh.extend(stale.into_iter().map(|candidate| Candidate {
+            stale: true,
+            ..candidate
+        }));
+        Ok(fresh)
+    }
+}
+
+struct Mobalytics;
+
+impl BuildSource for Mobalytics {
+    fn name(&self) -> &str {
+        "mobalytics"
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn fetch(&self, context: &Context) -> Result<Vec<Candidate>> {
+        get_mobalytics_info(context.conn, context.champ_id)
+    }
+}
+
+/// Fetch from one source, with matchup builds first and then builds for the player's role.
+/// Builds for other roles are only a fallback, otherwise the source's own order holds.
+pub fn fetch(source: &dyn BuildSource, context: &Context) -> Result<Vec<Candidate>> {
+    let mut found = source.fetch(context)?;
+    found.sort_by_key(|candidate| {
+        let other_role = context
+            .position
+            .is_some_and(|position| candidate.role.is_some_and(|role| role != position));
+        (context.opponents.rank(candidate.vs), other_role)
+    });
+    Ok(found)
+}
+
+pub struct Registry {
+    sources: Vec<Box<dyn BuildSource>>,
+}
+
+impl Registry {
+    /// New sources get added here.
+    fn builtin() -> Self {
+        let mut registry = Registry {
+            sources: Vec::new(),
+        };
+        registry.register(Box::new(Matchups));
+        registry.register(Box::new(Saved));
+        registry.register(Box::new(MatchHistory));
+        registry.register(Box::new(Folder));
+        registry.register(Box::new(Mobalytics));
+        registry
+    }
+
+    fn register(&mut self, source: Box<dyn BuildSource>) {
+        self.sources.push(source);
+        self.sources
+            .sort_by_key(|source| std::cmp::Reverse(source.priority()));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.sources.iter().map(|source| source.name()).collect()
+    }
+
+    /// The sources to use, in the order the config lists them, or by priority without a list.
+    pub fn enabled(&self) -> Vec<&dyn BuildSource> {
+        match &config().sources {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| self.sources.iter().find(|source| source.name() == name))
+                .map(|source| source.as_ref())
+                .collect(),
+            None => self.sources.iter().map(|source| source.as_ref()).collect(),
+        }
+    }
+
+    /// Pages from every enabled source, best first as ranked by `scoring`. A failing source is
+    /// skipped, and provider builds below the thresholds are marked dropped.
+    pub fn fetch(&self, context: &Context) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        let enabled = self.enabled();
+        for &source in &enabled {
+            log!("looking up on {}", source.name());
+            match fetch(source, context) {
+                Ok(mut found) => candidates.append(&mut found),
+                Err(e) => log!("couldn't get pages from {}: {}", source.name(), e),
+            }
+            log!("after {}, num pages: {}", source.name(), candidates.len());
+        }
+        for candidate in &mut candidates {
+            if let Some(reason) = scoring::below_threshold(candidate) {
+                log!("skipping {}: {}", candidate.page.name, reason);
+                candidate.dropped = Some(Dropped::BelowThreshold(reason));
+            }
+        }
+        let names: Vec<&str> = enabled.iter().map(|source| source.name()).collect();
+        scoring::rank(&mut candidates, &names, context);
+        candidates
+    }
+}
+
+/// Whether any source has a build against one of `enemies`.
+pub fn has_matchup_pages(context: &Context, enemies: &[u64]) -> bool {
+    registry()
+        .enabled()
+        .iter()
+        .filter(|source| source.has_matchups())
+        .filter_map(|source| source.fetch(context).ok())
+        .flatten()
+        .any(|candidate| candidate.vs.is_some_and(|vs| enemies.contains(&vs)))
+}
+
+/// Role names differ between the client ("middle", "utility") and build sites ("MID",
+/// "SUPPORT"), so they're compared by the client's name.
+pub fn normalize_role(role: &str) -> Option<&'static str> {
+    match role.to_ascii_lowercase().as_str() {
+        "top" => Some("top"),
+        "jungle" | "jg" => Some("jungle"),
+        "middle" | "mid" => Some("middle"),
+        "bottom" | "bot" | "adc" => Some("bottom"),
+        "utility" | "support" | "sup" => Some("utility"),
+        _ => None,
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::builtin)
+}
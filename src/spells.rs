@@ -0,0 +1,136 @@
+use crate::champ_id_by_name;
+use crate::config::{config, Config, Key, SpellRules};
+use crate::normalize_name;
+
+static SPELLS: &[(u64, &str)] = &[
+    (1, "Cleanse"),
+    (3, "Exhaust"),
+    (4, "Flash"),
+    (6, "Ghost"),
+    (7, "Heal"),
+    (11, "Smite"),
+    (12, "Teleport"),
+    (13, "Clarity"),
+    (14, "Ignite"),
+    (21, "Barrier"),
+    (32, "Mark"),
+];
+
+/// Spell ids by name, ignoring case and spaces. Ids are accepted as well.
+pub fn id_by_name(name: &str) -> Option<u64> {
+    if let Ok(id) = name.parse::<u64>() {
+        return Some(id);
+    }
+    let name = normalize_name(name);
+    SPELLS
+        .iter()
+        .find(|(_, spell)| normalize_name(spell) == name)
+        .map(|(id, _)| *id)
+}
+
+/// The spell's name, or its id for spells we don't know.
+pub fn name(id: u64) -> String {
+    SPELLS
+        .iter()
+        .find(|(spell, _)| *spell == id)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| id.to_string())
+}
+
+pub fn names() -> Vec<&'static str> {
+    SPELLS.iter().map(|(_, name)| *name).collect()
+}
+
+fn key_for(rules: &SpellRules, spell: u64) -> Option<Key> {
+    rules
+        .keys
+        .iter()
+        .find(|(name, _)| id_by_name(name) == Some(spell))
+        .map(|(_, key)| *key)
+}
+
+/// Put a spell pair in the order the placement rules want, champion rules first. When both
+/// spells want the same key the order is left alone.
+pub fn place(champ_id: u64, spells: (u64, u64)) -> (u64, u64) {
+    place_with(config(), champ_id, spells)
+}
+
+fn place_with(config: &Config, champ_id: u64, spells: (u64, u64)) -> (u64, u64) {
+    let champion = config
+        .champion_spells
+        .iter()
+        .find(|(name, _)| champ_id_by_name(name) == Some(champ_id))
+        .map(|(_, rules)| rules);
+    let keep_order = champion
+        .and_then(|rules| rules.keep_order)
+        .or(config.spells.keep_order)
+        .unwrap_or(false);
+    if keep_order {
+        return spells;
+    }
+    let key = |spell| {
+        champion
+            .and_then(|rules| key_for(rules, spell))
+            .or_else(|| key_for(&config.spells, spell))
+    };
+    match (key(spells.0), key(spells.1)) {
+        (Some(Key::F), second) if second != Some(Key::F) => (spells.1, spells.0),
+        (first, Some(Key::D)) if first != Some(Key::D) => (spells.1, spells.0),
+        _ => spells,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const FLASH: u64 = 4;
+    const SMITE: u64 = 11;
+    const IGNITE: u64 = 14;
+
+    #[test]
+    fn flash_goes_on_f_by_default() {
+        let config = Config::default();
+        assert_eq!(place_with(&config, 103, (FLASH, IGNITE)), (IGNITE, FLASH));
+        assert_eq!(place_with(&config, 103, (IGNITE, FLASH)), (IGNITE, FLASH));
+    }
+
+    #[test]
+    fn smite_on_d() {
+        let mut config = Config::default();
+        config.spells.keys.insert("Smite".to_string(), Key::D);
+        assert_eq!(place_with(&config, 103, (FLASH, SMITE)), (SMITE, FLASH));
+        assert_eq!(place_with(&config, 103, (SMITE, FLASH)), (SMITE, FLASH));
+    }
+
+    #[test]
+    fn keep_order_leaves_spells_alone() {
+        let mut config = Config::default();
+        config.spells.keep_order = Some(true);
+        assert_eq!(place_with(&config, 103, (FLASH, IGNITE)), (FLASH, IGNITE));
+    }
+
+    #[test]
+    fn champion_rules_win() {
+        let mut config = Config::default();
+        config.champion_spells.insert(
+            "Ahri".to_string(),
+            SpellRules {
+                keep_order: None,
+                keys: HashMap::from([("Flash".to_string(), Key::D)]),
+            },
+        );
+        assert_eq!(place_with(&config, 103, (IGNITE, FLASH)), (FLASH, IGNITE));
+        // Other champions still get the global rule
+        assert_eq!(place_with(&config, 1, (FLASH, IGNITE)), (IGNITE, FLASH));
+    }
+
+    #[test]
+    fn same_key_keeps_the_order() {
+        let mut config = Config::default();
+        config.spells.keys.insert("Ignite".to_string(), Key::F);
+        assert_eq!(place_with(&config, 103, (FLASH, IGNITE)), (FLASH, IGNITE));
+        assert_eq!(place_with(&config, 103, (IGNITE, FLASH)), (IGNITE, FLASH));
+    }
+}
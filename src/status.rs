@@ -0,0 +1,101 @@
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::sources::Opponents;
+use crate::{Candidate, GamePhase, RunePage};
+
+const LOG_LINES: usize = 500;
+
+static STATUS: Mutex<Status> = Mutex::new(Status::new());
+static CAPTURE_LOG: AtomicBool = AtomicBool::new(false);
+
+/// What the daemon currently knows about the client, for anything that wants to show it.
+pub struct Status {
+    pub phase: Option<GamePhase>,
+    pub game_mode: Option<String>,
+    pub champ_id: Option<u64>,
+    pub position: Option<&'static str>,
+    pub opponents: Opponents,
+    pub spells: Option<(u64, u64)>,
+    pub rune_page: Option<RunePage>,
+    /// Pages for the current champion, best first. Pages that were created have their id set.
+    pub candidates: Vec<Candidate>,
+    /// Pages that can't be picked, duplicates, invalid pages and the like
+    pub dropped: Vec<Candidate>,
+    pub log: VecDeque<String>,
+    /// Don't set up or save pages until resumed
+    pub paused: bool,
+    /// The player picked one of the candidates, so they're left alone until the champion changes
+    pub chosen: bool,
+    subscribers: Vec<Sender<(&'static str, String)>>,
+}
+
+impl Status {
+    const fn new() -> Self {
+        Status {
+            phase: None,
+            game_mode: None,
+            champ_id: None,
+            position: None,
+            opponents: Opponents {
+                enemies: Vec::new(),
+                laner: None,
+            },
+            spells: None,
+            rune_page: None,
+            candidates: Vec::new(),
+            dropped: Vec::new(),
+            log: VecDeque::new(),
+            paused: false,
+            chosen: false,
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+/// Lock the status. A panic while holding the lock can't leave it half updated in a way that
+/// matters, so poisoning is ignored.
+pub fn get() -> MutexGuard<'static, Status> {
+    STATUS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Keep log lines in the status instead of printing them, for when stdout is taken.
+#[cfg(feature = "tui")]
+pub fn capture_log() {
+    CAPTURE_LOG.store(true, Ordering::Relaxed);
+}
+
+pub fn log(line: String) {
+    if !CAPTURE_LOG.load(Ordering::Relaxed) {
+        println!("{}", line);
+        return;
+    }
+    let mut status = get();
+    status.log.push_back(line);
+    if status.log.len() > LOG_LINES {
+        status.log.pop_front();
+    }
+}
+
+/// Get every event published from now on, as the event name and its data as JSON.
+pub fn subscribe() -> Receiver<(&'static str, String)> {
+    let (sender, receiver) = channel();
+    get().subscribers.push(sender);
+    receiver
+}
+
+pub fn publish(event: &'static str, data: Value) {
+    let data = data.to_string();
+    get()
+        .subscribers
+        .retain(|subscriber| subscriber.send((event, data.clone())).is_ok());
+}
+
+pub fn set_paused(paused: bool) {
+    get().paused = paused;
+    publish("paused", serde_json::json!({ "paused": paused }));
+    log!("{}", if paused { "paused" } else { "resumed" });
+}
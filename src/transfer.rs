@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::{champ_id_by_name, get_stored_page, save_rune_page, set_pinned, RunePage, CHAMPIONS};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+/// What `import` does when the database already has a page for the same champion and mode.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    /// Leave the stored page alone
+    Keep,
+    /// Overwrite the stored page with the one from the file
+    Replace,
+    /// Keep whichever page was saved last
+    Newest,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PageFile {
+    pages: Vec<StoredPage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredPage {
+    champion: String,
+    game_mode: String,
+    spells: [u64; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    patch: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    /// Seconds since the epoch, missing in files exported before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    saved_at: Option<i64>,
+    page: RunePage,
+}
+
+/// Whether the imported page is newer than the stored one. Pages from sources other than the
+/// client have no `last_modified`, so that is only used when either side has no `saved_at`.
+fn is_newer(imported: &StoredPage, stored: (Option<i64>, &RunePage)) -> bool {
+    match (imported.saved_at, stored.0) {
+        (Some(imported), Some(stored)) => imported > stored,
+        _ => imported.page.last_modified > stored.1.last_modified,
+    }
+}
+
+fn stored_saved_at(conn: &Connection, champ_id: u64, game_mode: &str) -> Result<Option<i64>> {
+    Ok(conn.query_row(
+        "select saved_at from rune_pages where champ_id = ?1 and game_mode = ?2",
+        params![champ_id as i64, game_mode],
+        |row| row.get(0),
+    )?)
+}
+
+fn pick_format(file: &Path, format: Option<Format>) -> Format {
+    format.unwrap_or_else(|| match file.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => Format::Toml,
+        _ => Format::Json,
+    })
+}
+
+pub fn export(conn: &Connection, file: &Path, format: Option<Format>) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "select champ_id, game_mode, spell1_id, spell2_id, page, patch, pinned, saved_at
+            from rune_pages order by champ_id, game_mode",
+    )?;
+    let pages = stmt
+        .query_map([], |row| {
+            let champ_id: i64 = row.get(0)?;
+            let game_mode: String = row.get(1)?;
+            let spells: (i64, i64) = (row.get(2)?, row.get(3)?);
+            let page: String = row.get(4)?;
+            let patch: Option<String> = row.get(5)?;
+            let pinned: bool = row.get(6)?;
+            let saved_at: Option<i64> = row.get(7)?;
+            Ok((
+                champ_id as u64,
+                game_mode,
+                spells,
+                page,
+                patch,
+                pinned,
+                saved_at,
+            ))
+        })?
+        .map(|row| {
+            let (champ_id, game_mode, spells, page, patch, pinned, saved_at) = row?;
+            let champion = CHAMPIONS
+                .get(&champ_id)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| champ_id.to_string());
+            Ok(StoredPage {
+                champion,
+                game_mode,
+                spells: [spells.0 as u64, spells.1 as u64],
+                patch,
+                pinned,
+                saved_at,
+                page: serde_json::from_str(&page)?,
+            })
+        })
+        .collect::<Result<Vec<StoredPage>>>()?;
+
+    let count = pages.len();
+    let pages = PageFile { pages };
+    let contents = match pick_format(file, format) {
+        Format::Json => serde_json::to_string_pretty(&pages)?,
+        Format::Toml => toml::to_string_pretty(&pages)?,
+    };
+    fs::write(file, contents).with_context(|| format!("failed to write {}", file.display()))?;
+    println!("exported {} pages to {}", count, file.display());
+    Ok(())
+}
+
+pub fn import(
+    conn: &Connection,
+    file: &Path,
+    format: Option<Format>,
+    on_conflict: Conflict,
+    dry_run: bool,
+) -> Result<()> {
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let pages: PageFile = match pick_format(file, format) {
+        Format::Json => serde_json::from_str(&contents)?,
+        Format::Toml => toml::from_str(&contents)?,
+    };
+
+    let (mut imported, mut kept) = (0, 0);
+    for stored in pages.pages {
+        let champ_id = champ_id_by_name(&stored.champion)
+            .ok_or_else(|| anyhow!("unknown champion: {}", stored.champion))?;
+        let existing = get_stored_page(conn, champ_id, &stored.game_mode)?;
+        let replace = match (existing, on_conflict) {
+            (None, _) => true,
+            (Some(_), Conflict::Keep) => false,
+            (Some(_), Conflict::Replace) => true,
+            (Some((_, page)), Conflict::Newest) => {
+                let saved_at = stored_saved_at(conn, champ_id, &stored.game_mode)?;
+                is_newer(&stored, (saved_at, &page))
+            }
+        };
+        if replace && dry_run {
+            println!(
+                "would import page for {} ({})",
+                stored.champion, stored.game_mode
+            );
+            imported += 1;
+        } else if replace {
+            let spells = (stored.spells[0], stored.spells[1]);
+            save_rune_page(
+                conn,
+                champ_id,
+                spells,
+                &stored.game_mode,
+                &stored.page,
+                stored.patch.as_deref(),
+            )?;
+            // Keep the exported time, not the time of the import
+            if let Some(saved_at) = stored.saved_at {
+                conn.execute(
+                    "update rune_pages set saved_at = ?1 where champ_id = ?2 and game_mode = ?3",
+                    params![saved_at, champ_id as i64, stored.game_mode],
+                )?;
+            }
+            if stored.pinned {
+                set_pinned(conn, champ_id, Some(&stored.game_mode), true)?;
+            }
+            imported += 1;
+        } else {
+            println!(
+                "keeping stored page for {} ({})",
+                stored.champion, stored.game_mode
+            );
+            kept += 1;
+        }
+    }
+    let verb = if dry_run { "would import" } else { "imported" };
+    println!("{} {} pages, kept {} stored pages", verb, imported, kept);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(saved_at: Option<i64>, last_modified: u64) -> StoredPage {
+        StoredPage {
+            champion: "Ahri".to_string(),
+            game_mode: "CLASSIC".to_string(),
+            spells: [4, 14],
+            patch: None,
+            pinned: false,
+            saved_at,
+            page: RunePage {
+                last_modified,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn newest_compares_saved_at() {
+        // Pages saved outside the client have no last_modified
+        let existing = page(Some(100), 5_000).page;
+        assert!(is_newer(&page(Some(200), 0), (Some(100), &existing)));
+        assert!(!is_newer(&page(Some(50), 9_000), (Some(100), &existing)));
+    }
+
+    #[test]
+    fn newest_falls_back_to_last_modified() {
+        let existing = page(None, 5_000).page;
+        assert!(is_newer(&page(Some(200), 9_000), (None, &existing)));
+        assert!(!is_newer(&page(None, 1_000), (Some(100), &existing)));
+    }
+}
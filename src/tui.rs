@@ -0,0 +1,184 @@
+use anyhow::{anyhow, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use rusqlite::Connection;
+use std::process;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::lcu::LCUClient;
+use crate::{make_candidate_current, save_candidate, spells, status, Job, CHAMPIONS};
+
+/// Take over the terminal and show the dashboard. The daemon keeps running on the calling
+/// thread, quitting the dashboard exits the whole program. Picking and saving pages is sent to
+/// the daemon as `jobs`.
+pub fn start(jobs: Sender<Job>) -> Result<()> {
+    status::capture_log();
+    thread::spawn(move || {
+        let terminal = ratatui::init();
+        let result = ui_loop(terminal, &jobs);
+        ratatui::restore();
+        if let Err(e) = result {
+            eprintln!("dashboard failed: {}", e);
+            process::exit(1);
+        }
+        process::exit(0);
+    });
+    Ok(())
+}
+
+fn ui_loop(mut terminal: DefaultTerminal, jobs: &Sender<Job>) -> Result<()> {
+    let mut selected = ListState::default().with_selected(Some(0));
+    loop {
+        terminal.draw(|frame| draw(frame, &mut selected))?;
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => selected.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => selected.select_previous(),
+            KeyCode::Enter => {
+                let result = act(jobs, selected.selected(), |lcuclient, _, index, dry_run| {
+                    if let Err(e) = make_candidate_current(lcuclient, index, dry_run) {
+                        log!("couldn't make page current: {}", e);
+                    }
+                });
+                if let Err(e) = result {
+                    log!("couldn't make page current: {}", e);
+                }
+            }
+            KeyCode::Char('s') => {
+                let result = act(
+                    jobs,
+                    selected.selected(),
+                    |lcuclient, conn, index, dry_run| {
+                        if let Err(e) = save_candidate(lcuclient, conn, index, dry_run) {
+                            log!("couldn't save page: {}", e);
+                        }
+                    },
+                );
+                if let Err(e) = result {
+                    log!("couldn't save page: {}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, selected: &mut ListState) {
+    let status = status::get();
+    let [header, candidates, log, help] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let champion = status
+        .champ_id
+        .and_then(|id| CHAMPIONS.get(&id).copied())
+        .unwrap_or("-");
+    let spells = status
+        .spells
+        .map(|(d, f)| format!("{} {}", spells::name(d), spells::name(f)))
+        .unwrap_or_else(|| "-".to_string());
+    let phase = status
+        .phase
+        .map(|phase| format!("{:?}", phase))
+        .unwrap_or_else(|| "-".to_string());
+    let lines = vec![
+        Line::from(format!(
+            "Phase: {}   Mode: {}{}",
+            phase,
+            status.game_mode.as_deref().unwrap_or("-"),
+            if status.paused { "   (paused)" } else { "" }
+        )),
+        Line::from(format!("Champion: {}   Spells: {}", champion, spells)),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("rune_pager")),
+        header,
+    );
+
+    let current = status.rune_page.as_ref().map(|page| page.id);
+    let items: Vec<ListItem> = status
+        .candidates
+        .iter()
+        .map(|candidate| {
+            let win_rate = candidate
+                .win_rate
+                .map(|rate| format!("{:.1}%", rate * 100.0))
+                .unwrap_or_default();
+            let mark = if candidate.page.id != 0 && Some(candidate.page.id) == current {
+                "*"
+            } else {
+                " "
+            };
+            ListItem::new(format!(
+                "{} {:<10} {:>6}  {}",
+                mark, candidate.source, win_rate, candidate.page.name
+            ))
+        })
+        .collect();
+    let title = format!(
+        "Pages ({})",
+        status
+            .rune_page
+            .as_ref()
+            .map_or("-", |page| page.name.as_str())
+    );
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        candidates,
+        selected,
+    );
+
+    let height = log.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = status
+        .log
+        .iter()
+        .skip(status.log.len().saturating_sub(height))
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Log")),
+        log,
+    );
+
+    frame.render_widget(
+        Paragraph::new("up/down: select   enter: make current   s: save as stored page   q: quit"),
+        help,
+    );
+}
+
+/// Have the daemon do something with the selected page. Fails right away if there's no client
+/// to do it in, rather than leaving it queued until one shows up.
+fn act(
+    jobs: &Sender<Job>,
+    index: Option<usize>,
+    action: impl FnOnce(&LCUClient, &Connection, usize, bool) + Send + 'static,
+) -> Result<()> {
+    let index = index.ok_or_else(|| anyhow!("no page selected"))?;
+    LCUClient::try_new()?;
+    jobs.send(Box::new(move |lcuclient, conn, dry_run| {
+        action(lcuclient, conn, index, dry_run)
+    }))
+    .map_err(|_| anyhow!("the event loop isn't running"))?;
+    Ok(())
+}